@@ -1,5 +1,10 @@
+use crate::compression::Compression;
+use crate::filter::{self, Filter};
 use crate::formats::Format;
+use globset::Glob;
+use regex::Regex;
 use std::path::PathBuf;
+use std::str::FromStr;
 use structopt::StructOpt;
 use strum::VariantNames;
 use strum_macros::{Display, EnumString, EnumVariantNames};
@@ -21,14 +26,28 @@ pub enum Command {
         #[structopt(short = "i", long = "ignore-hidden", help = "Ignore hidden files")]
         ignore_hidden: bool,
 
+        #[structopt(
+            short = "x",
+            long = "one-file-system",
+            help = "Don't descend into directories on other filesystems"
+        )]
+        one_file_system: bool,
+
         #[structopt(short = "a", long = "actual-size", help = "Calculate the actual size")]
         actual_size: bool,
 
         #[structopt(short = "o", long = "output", parse(from_os_str))]
         output: Option<PathBuf>,
 
-        #[structopt(parse(from_os_str))]
-        path: PathBuf,
+        #[structopt(parse(from_os_str), required_unless = "roots-from")]
+        paths: Vec<PathBuf>,
+
+        #[structopt(
+            long = "roots-from",
+            parse(from_os_str),
+            help = "Also scan the paths listed in this file, one per line"
+        )]
+        roots_from: Option<PathBuf>,
 
         #[structopt(
         short = "f",
@@ -38,8 +57,76 @@ pub enum Command {
         )]
         format: Format,
 
+        #[structopt(
+            long = "compress",
+            possible_values = &Compression::VARIANTS,
+            help = "Compress the output, instead of using the output file's extension such as .json.zst"
+        )]
+        compress: Option<Compression>,
+
         #[structopt(short = "d", long = "depth", help = "Directory depth to scan")]
         depth: Option<usize>,
+
+        #[structopt(flatten)]
+        filter: FilterArgs,
+
+        #[structopt(
+            long = "extensions",
+            help = "Record the number and size of files for each extension"
+        )]
+        extensions: bool,
+
+        #[structopt(
+            long = "owners",
+            help = "Record the number and size of files for each owner and group"
+        )]
+        owners: bool,
+
+        #[structopt(long = "histogram", help = "Record a histogram of file sizes")]
+        histogram: bool,
+
+        #[structopt(
+            long = "age-buckets",
+            require_delimiter = true,
+            help = "Record files not modified or accessed in these numbers of days, e.g 30,90,365"
+        )]
+        age_buckets: Vec<u32>,
+
+        #[structopt(
+            long = "largest-files",
+            help = "Record this many of the largest files in each directory"
+        )]
+        largest_files: Option<usize>,
+
+        #[structopt(
+            long = "checkpoint",
+            parse(from_os_str),
+            requires = "output",
+            help = "Periodically save the progress of the scan to this file"
+        )]
+        checkpoint: Option<PathBuf>,
+
+        #[structopt(
+            long = "resume",
+            requires = "checkpoint",
+            help = "Resume an interrupted scan from the checkpoint file"
+        )]
+        resume: bool,
+
+        #[structopt(
+            long = "baseline",
+            parse(from_os_str),
+            conflicts_with = "depth",
+            help = "A previous scan, in the same format, to reuse the totals of unchanged directories from"
+        )]
+        baseline: Option<PathBuf>,
+
+        #[structopt(
+            long = "errors",
+            parse(from_os_str),
+            help = "Write a JSON record for each path that couldn't be read to this file"
+        )]
+        errors: Option<PathBuf>,
     },
     #[structopt(about = "Stream file paths to stdout from a given set of directories")]
     Stream {
@@ -52,11 +139,67 @@ pub enum Command {
         #[structopt(parse(from_os_str))]
         path: PathBuf,
 
+        #[structopt(
+            short = "x",
+            long = "one-file-system",
+            help = "Don't descend into directories on other filesystems"
+        )]
+        one_file_system: bool,
+
         #[structopt(short = "n", long = "no-size", help = "Don't output file sizes")]
         no_size: bool,
 
         #[structopt(short = "p", long = "only-paths", help = "Only output file paths")]
         only_paths: bool,
+
+        #[structopt(flatten)]
+        filter: FilterArgs,
+    },
+    #[structopt(about = "Find duplicate files")]
+    Dupes {
+        #[structopt(short = "t", long = "threads")]
+        threads: Option<usize>,
+
+        #[structopt(short = "i", long = "ignore-hidden", help = "Ignore hidden files")]
+        ignore_hidden: bool,
+
+        #[structopt(
+            short = "x",
+            long = "one-file-system",
+            help = "Don't descend into directories on other filesystems"
+        )]
+        one_file_system: bool,
+
+        #[structopt(short = "o", long = "output", parse(from_os_str))]
+        output: Option<PathBuf>,
+
+        #[structopt(parse(from_os_str))]
+        path: PathBuf,
+
+        #[structopt(
+        short = "f",
+        long = "format",
+        default_value = "json",
+        possible_values = &Format::VARIANTS
+        )]
+        format: Format,
+
+        #[structopt(
+            long = "compress",
+            possible_values = &Compression::VARIANTS,
+            help = "Compress the output, instead of using the output file's extension such as .json.zst"
+        )]
+        compress: Option<Compression>,
+
+        #[structopt(flatten)]
+        filter: FilterArgs,
+
+        #[structopt(
+            long = "errors",
+            parse(from_os_str),
+            help = "Write a JSON record for each path that couldn't be read to this file"
+        )]
+        errors: Option<PathBuf>,
     },
     #[structopt(about = "Parse results files")]
     Parse {
@@ -75,10 +218,10 @@ pub enum Command {
         #[structopt(
         short = "f",
         long = "format",
-        default_value = "json",
-        possible_values = &Format::VARIANTS
+        possible_values = &Format::VARIANTS,
+        help = "The format of the input, detected from its name or contents if not given"
         )]
-        format: Format,
+        format: Option<Format>,
 
         #[structopt(
         short = "s",
@@ -87,9 +230,202 @@ pub enum Command {
         possible_values = &SortType::VARIANTS
         )]
         sort: SortType,
+
+        #[structopt(
+        long = "by",
+        help = "Break down each directory by a value recorded during the scan",
+        possible_values = &Breakdown::VARIANTS
+        )]
+        by: Option<Breakdown>,
+
+        #[structopt(
+            long = "histogram",
+            help = "Show a histogram of file sizes under the prefix",
+            conflicts_with = "by"
+        )]
+        histogram: bool,
+
+        #[structopt(
+            long = "cold",
+            help = "Show data not modified in this many days, from a scan with --age-buckets",
+            conflicts_with_all = &["by", "histogram"]
+        )]
+        cold: Option<u32>,
+
+        #[structopt(
+            long = "earliest",
+            help = "Include the earliest created, accessed and modified times"
+        )]
+        earliest: bool,
+
+        #[structopt(
+            long = "largest-files",
+            help = "Show the largest files under the prefix",
+            conflicts_with_all = &["by", "histogram", "cold"]
+        )]
+        largest_files: bool,
+
+        #[structopt(
+            long = "metadata",
+            help = "Show how the input file was produced, from its metadata header",
+            conflicts_with_all = &["by", "histogram", "cold", "largest-files"]
+        )]
+        metadata: bool,
+
+        #[structopt(
+            long = "skip-invalid",
+            help = "Report entries that can't be parsed and continue, instead of stopping"
+        )]
+        skip_invalid: bool,
+    },
+    #[structopt(about = "Merge results files into a single file")]
+    Merge {
+        #[structopt(short = "o", long = "output", parse(from_os_str))]
+        output: Option<PathBuf>,
+
+        #[structopt(
+        short = "f",
+        long = "format",
+        default_value = "json",
+        possible_values = &Format::VARIANTS
+        )]
+        format: Format,
+
+        #[structopt(
+            long = "compress",
+            possible_values = &Compression::VARIANTS,
+            help = "Compress the output, instead of using the output file's extension such as .json.zst"
+        )]
+        compress: Option<Compression>,
+
+        #[structopt(
+        long = "input-format",
+        default_value = "json",
+        possible_values = &Format::VARIANTS,
+        help = "The format of inputs whose format can't be detected from their name or contents"
+        )]
+        input_format: Format,
+
+        #[structopt(parse(from_os_str), required = true)]
+        inputs: Vec<PathBuf>,
+
+        #[structopt(
+            long = "skip-invalid",
+            help = "Report entries that can't be parsed and continue, instead of stopping"
+        )]
+        skip_invalid: bool,
+    },
+    #[structopt(about = "Compare two results files")]
+    Diff {
+        #[structopt(short = "d", long = "depth", default_value = "1")]
+        depth: usize,
+
+        #[structopt(short = "p", long = "prefix", default_value = "")]
+        prefix: String,
+
+        #[structopt(short = "l", long = "limit")]
+        limit: Option<usize>,
+
+        #[structopt(parse(from_os_str), help = "The earlier results file")]
+        old: PathBuf,
+
+        #[structopt(parse(from_os_str), help = "The later results file")]
+        new: PathBuf,
+
+        #[structopt(
+        short = "f",
+        long = "format",
+        possible_values = &Format::VARIANTS,
+        help = "The format of the input, detected from its name or contents if not given"
+        )]
+        format: Option<Format>,
+
+        #[structopt(
+        short = "s",
+        long = "sort",
+        default_value = "growth",
+        possible_values = &DiffSortType::VARIANTS,
+        help = "Sort by name, the current files or size, or the growth in size"
+        )]
+        sort: DiffSortType,
+
+        #[structopt(
+            long = "skip-invalid",
+            help = "Report entries that can't be parsed and continue, instead of stopping"
+        )]
+        skip_invalid: bool,
     },
 }
 
+#[derive(StructOpt)]
+pub struct FilterArgs {
+    #[structopt(
+        long = "include",
+        number_of_values = 1,
+        parse(try_from_str = filter::parse_glob),
+        help = "Only include files matching this glob"
+    )]
+    include: Vec<Glob>,
+
+    #[structopt(
+        long = "exclude",
+        number_of_values = 1,
+        parse(try_from_str = filter::parse_glob),
+        help = "Exclude files and directories matching this glob"
+    )]
+    exclude: Vec<Glob>,
+
+    #[structopt(
+        long = "include-regex",
+        number_of_values = 1,
+        help = "Only include files with a path matching this regex"
+    )]
+    include_regex: Vec<Regex>,
+
+    #[structopt(
+        long = "exclude-regex",
+        number_of_values = 1,
+        help = "Exclude files and directories with a path matching this regex"
+    )]
+    exclude_regex: Vec<Regex>,
+}
+
+impl FilterArgs {
+    // The filters as they were given, such as `--include=*.log`.
+    pub fn to_args(&self) -> Vec<String> {
+        let globs = |option, globs: &[Glob]| {
+            globs
+                .iter()
+                .map(|glob| format!("--{}={}", option, glob.glob()))
+                .collect::<Vec<_>>()
+        };
+        let regexes = |option, regexes: &[Regex]| {
+            regexes
+                .iter()
+                .map(|regex| format!("--{}={}", option, regex.as_str()))
+                .collect::<Vec<_>>()
+        };
+        [
+            globs("include", &self.include),
+            globs("exclude", &self.exclude),
+            regexes("include-regex", &self.include_regex),
+            regexes("exclude-regex", &self.exclude_regex),
+        ]
+        .concat()
+    }
+}
+
+impl From<FilterArgs> for Filter {
+    fn from(args: FilterArgs) -> Filter {
+        Filter::new(
+            args.include,
+            args.exclude,
+            args.include_regex,
+            args.exclude_regex,
+        )
+    }
+}
+
 #[derive(EnumString, EnumVariantNames, Display)]
 #[strum(serialize_all = "kebab_case")]
 pub enum SortType {
@@ -97,3 +433,56 @@ pub enum SortType {
     Files,
     Size,
 }
+
+// Either one of the orders used by `parse`, applied to the new totals, or by how much each
+// directory has grown.
+pub enum DiffSortType {
+    Scan(SortType),
+    Growth,
+    RelativeGrowth,
+}
+
+impl VariantNames for DiffSortType {
+    const VARIANTS: &'static [&'static str] =
+        &["name", "files", "size", "growth", "relative-growth"];
+}
+
+impl FromStr for DiffSortType {
+    type Err = strum::ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "growth" => Ok(DiffSortType::Growth),
+            "relative-growth" => Ok(DiffSortType::RelativeGrowth),
+            other => other.parse().map(DiffSortType::Scan),
+        }
+    }
+}
+
+#[derive(EnumString, EnumVariantNames, Display)]
+#[strum(serialize_all = "kebab_case")]
+pub enum Breakdown {
+    Extension,
+    Owner,
+    Group,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diff_sort_types_include_every_sort_type() {
+        for name in SortType::VARIANTS {
+            assert!(DiffSortType::VARIANTS.contains(name));
+        }
+        for name in DiffSortType::VARIANTS {
+            assert!(
+                DiffSortType::from_str(name).is_ok(),
+                "{} doesn't parse",
+                name
+            );
+        }
+        assert!(DiffSortType::from_str("age").is_err());
+    }
+}
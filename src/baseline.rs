@@ -0,0 +1,82 @@
+use crate::directory_stat::DirectoryStat;
+use crate::formats::{Format, ParseError};
+use crate::options::ScanOptions;
+use chrono::{DateTime, Utc};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+// A previous scan, used to avoid reading the metadata of every file within directories that
+// haven't changed. A directory is considered unchanged if its modification time and the number
+// of entries within it are the same as in the previous scan.
+pub struct Baseline {
+    stats: HashMap<PathBuf, DirectoryStat>,
+    // Directories that the walker has decided to reuse, which haven't been finished yet.
+    reused: Mutex<HashSet<PathBuf>>,
+}
+
+impl Baseline {
+    // Only a scan with the same options can be reused, as they change what each directory's
+    // statistics include.
+    pub fn load(
+        path: PathBuf,
+        format: &Format,
+        options: &ScanOptions,
+    ) -> Result<Baseline, ParseError> {
+        if let Some(metadata) = format.read_header(&path)? {
+            let differences = metadata.options.differences(options);
+            if !differences.is_empty() {
+                return Err(ParseError::InvalidFile(format!(
+                    "The baseline was scanned with different {}",
+                    differences.join(", ")
+                )));
+            }
+        }
+        let mut stats = HashMap::new();
+        for stat in format.parse_file(&path)? {
+            let stat = stat?;
+            if stat.directory_modified.is_some() {
+                stats.insert(stat.path.clone(), stat);
+            }
+        }
+        Ok(Baseline {
+            stats,
+            reused: Mutex::new(HashSet::new()),
+        })
+    }
+
+    // Called by the walker once the contents of a directory have been read.
+    pub fn try_reuse(&self, path: &Path, child_count: usize) -> bool {
+        let previous = match self.stats.get(path) {
+            Some(previous) if previous.child_count == child_count as u64 => previous,
+            _ => return false,
+        };
+        let modified: Option<DateTime<Utc>> = std::fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .map(|modified| modified.into())
+            .ok();
+        if modified.is_none() || modified != previous.directory_modified {
+            return false;
+        }
+        self.reused.lock().unwrap().insert(path.to_path_buf());
+        true
+    }
+
+    pub fn is_reused(&self, path: &Path) -> bool {
+        self.reused.lock().unwrap().contains(path)
+    }
+
+    // Called once a directory has been finished, returning the previous statistics if the
+    // walker decided to reuse them.
+    pub fn take_reused(&self, path: &Path) -> Option<&DirectoryStat> {
+        if self.reused.lock().unwrap().remove(path) {
+            self.stats.get(path)
+        } else {
+            None
+        }
+    }
+
+    pub fn get(&self, path: &Path) -> Option<&DirectoryStat> {
+        self.stats.get(path)
+    }
+}
@@ -0,0 +1,106 @@
+use crate::compression::{Compression, Encoder};
+use crate::directory_stat::{DirectoryStat, FileStat};
+use crate::options::ScanOptions;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+// How often the state of a scan is saved to the checkpoint file.
+pub const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(30);
+
+// Everything needed to continue a scan from the point that it was saved. Directories are walked
+// in a deterministic order, so every directory before `resume_from` has either been written to
+// the output or is one of the parents of `resume_from` in `stack`.
+#[derive(Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub roots: Vec<PathBuf>,
+    pub format: String,
+    pub options: ScanOptions,
+    pub started: SystemTime,
+    // The length of the output file when the checkpoint was saved. Anything written after this
+    // is discarded when resuming.
+    pub output_length: u64,
+    pub resume_from: PathBuf,
+    pub stack: Vec<DirectoryStat>,
+    pub seen_hard_links: Vec<(u64, u64)>,
+    pub largest_files: Vec<FileStat>,
+}
+
+impl Checkpoint {
+    // There is no checkpoint once a scan has completed, as there is nothing left to resume.
+    pub fn load(path: &PathBuf) -> io::Result<Option<Checkpoint>> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        Ok(Some(serde_json::from_reader(BufReader::new(file))?))
+    }
+
+    // Open the output to continue writing it, discarding anything written after the checkpoint.
+    // Checkpointed output is never compressed.
+    pub fn open_output(&self, path: &Path) -> io::Result<Encoder> {
+        let file = OpenOptions::new().append(true).open(path)?;
+        file.set_len(self.output_length)?;
+        let writer = BufWriter::with_capacity(1024 * 1024, file);
+        Ok(Compression::None.wrap_writer(writer))
+    }
+}
+
+pub struct Checkpointer {
+    path: PathBuf,
+    pub output: PathBuf,
+    pub roots: Vec<PathBuf>,
+    pub format: String,
+    pub options: ScanOptions,
+    // How often the checkpoint is saved, which is CHECKPOINT_INTERVAL other than in tests.
+    interval: Duration,
+    last_saved: Instant,
+}
+
+impl Checkpointer {
+    pub fn new(
+        path: PathBuf,
+        output: PathBuf,
+        roots: Vec<PathBuf>,
+        format: String,
+        options: ScanOptions,
+        interval: Duration,
+    ) -> Checkpointer {
+        Checkpointer {
+            path,
+            output,
+            roots,
+            format,
+            options,
+            interval,
+            last_saved: Instant::now(),
+        }
+    }
+
+    pub fn is_due(&self) -> bool {
+        self.last_saved.elapsed() >= self.interval
+    }
+
+    pub fn save(&mut self, checkpoint: &Checkpoint) -> io::Result<()> {
+        // Write to a temporary file first, so an interrupted save doesn't leave a corrupt
+        // checkpoint behind.
+        let temp_path = self.path.with_extension("tmp");
+        let mut writer = BufWriter::new(File::create(&temp_path)?);
+        serde_json::to_writer(&mut writer, checkpoint)?;
+        writer.flush()?;
+        fs::rename(&temp_path, &self.path)?;
+        self.last_saved = Instant::now();
+        Ok(())
+    }
+
+    // Called once the scan has completed, as there is nothing left to resume.
+    pub fn remove(&self) -> io::Result<()> {
+        match fs::remove_file(&self.path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
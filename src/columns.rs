@@ -0,0 +1,136 @@
+// The typed columns used by CSV and the formats with a fixed schema. These must list every field
+// of `DirectoryStat` and `DuplicateGroup`, or the field isn't written.
+
+use crate::directory_stat::DirectoryStat;
+use crate::dupes::DuplicateGroup;
+use serde::Serialize;
+use serde_json::Value;
+use std::io;
+
+pub type Columns = &'static [(&'static str, ColumnType)];
+
+#[derive(Clone, Copy)]
+pub enum ColumnType {
+    Integer,
+    Boolean,
+    Text,
+    Timestamp,
+    // Nested values such as maps and lists, stored as JSON text in the same way as CSV.
+    Json,
+}
+
+// A kind of record written as output, either by a scan or when finding duplicates.
+pub trait Record: Serialize {
+    const COLUMNS: Columns;
+    // The SQLite table it is written to.
+    const TABLE: &'static str;
+}
+
+// The value of each of a record's columns, in order. Fields that are skipped are null.
+pub fn to_row<R: Record>(record: &R) -> io::Result<Vec<Value>> {
+    let mut fields = match serde_json::to_value(record)? {
+        Value::Object(fields) => fields,
+        _ => {
+            let message = "Only objects can be written as rows";
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+        }
+    };
+    Ok(R::COLUMNS
+        .iter()
+        .map(|(name, _)| fields.remove(*name).unwrap_or(Value::Null))
+        .collect())
+}
+
+impl Record for DirectoryStat {
+    const COLUMNS: Columns = STAT_COLUMNS;
+    const TABLE: &'static str = "directories";
+}
+
+impl Record for DuplicateGroup {
+    const COLUMNS: Columns = DUPLICATE_COLUMNS;
+    const TABLE: &'static str = "duplicates";
+}
+
+pub const STAT_COLUMNS: Columns = &[
+    ("total_size", ColumnType::Integer),
+    ("file_count", ColumnType::Integer),
+    ("largest_file_size", ColumnType::Integer),
+    ("path", ColumnType::Text),
+    ("latest_created", ColumnType::Timestamp),
+    ("latest_accessed", ColumnType::Timestamp),
+    ("latest_modified", ColumnType::Timestamp),
+    ("earliest_created", ColumnType::Timestamp),
+    ("earliest_accessed", ColumnType::Timestamp),
+    ("earliest_modified", ColumnType::Timestamp),
+    ("directory_modified", ColumnType::Timestamp),
+    ("child_count", ColumnType::Integer),
+    ("reused", ColumnType::Boolean),
+    ("deduplicated_size", ColumnType::Integer),
+    ("recursive_total_size", ColumnType::Integer),
+    ("recursive_file_count", ColumnType::Integer),
+    ("recursive_largest_file_size", ColumnType::Integer),
+    ("recursive_latest_created", ColumnType::Timestamp),
+    ("recursive_latest_accessed", ColumnType::Timestamp),
+    ("recursive_latest_modified", ColumnType::Timestamp),
+    ("skipped_mount_point", ColumnType::Boolean),
+    ("errors", ColumnType::Integer),
+    ("incomplete", ColumnType::Boolean),
+    ("extensions", ColumnType::Json),
+    ("owners", ColumnType::Json),
+    ("groups", ColumnType::Json),
+    ("size_histogram", ColumnType::Json),
+    ("age_buckets", ColumnType::Json),
+    ("largest_files", ColumnType::Json),
+];
+
+pub const DUPLICATE_COLUMNS: Columns = &[
+    ("size", ColumnType::Integer),
+    ("count", ColumnType::Integer),
+    ("wasted_size", ColumnType::Integer),
+    ("hash", ColumnType::Text),
+    ("paths", ColumnType::Json),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::directory_stat::tests::stat;
+    use crate::directory_stat::{AgeStat, FileStat};
+    use std::collections::BTreeSet;
+    use std::path::PathBuf;
+
+    fn keys(record: &impl Serialize) -> BTreeSet<String> {
+        match serde_json::to_value(record).unwrap() {
+            Value::Object(fields) => fields.keys().cloned().collect(),
+            _ => panic!("Records are objects"),
+        }
+    }
+
+    fn names(columns: Columns) -> BTreeSet<String> {
+        columns.iter().map(|(name, _)| name.to_string()).collect()
+    }
+
+    #[test]
+    fn columns_match_the_fields_of_each_record() {
+        let mut directory = stat("/a", 10, 1);
+        directory.extensions = Some(Default::default());
+        directory.owners = Some(Default::default());
+        directory.groups = Some(Default::default());
+        directory.size_histogram = Some(vec![1]);
+        directory.age_buckets = Some(vec![(30, AgeStat::default())].into_iter().collect());
+        directory.largest_files = Some(vec![FileStat {
+            path: PathBuf::from("/a/f"),
+            size: 10,
+        }]);
+        assert_eq!(keys(&directory), names(STAT_COLUMNS));
+
+        let group = DuplicateGroup {
+            size: 10,
+            count: 2,
+            wasted_size: 10,
+            hash: "hash".to_string(),
+            paths: vec![PathBuf::from("/a/f"), PathBuf::from("/b/f")],
+        };
+        assert_eq!(keys(&group), names(DUPLICATE_COLUMNS));
+    }
+}
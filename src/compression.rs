@@ -0,0 +1,150 @@
+use flate2::write::GzEncoder;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use strum_macros::{Display, EnumString, EnumVariantNames};
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+#[derive(EnumString, EnumVariantNames, Display, Clone, Copy, PartialEq)]
+#[strum(serialize_all = "kebab_case")]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    // The compression matching a file's extension, such as `.json.zst`.
+    pub fn from_extension(path: &Path) -> Compression {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("gz") => Compression::Gzip,
+            Some("zst") => Compression::Zstd,
+            _ => Compression::None,
+        }
+    }
+
+    // The --compress option if given, otherwise the compression matching the output file.
+    pub fn for_output(compress: Option<Compression>, output: &Option<PathBuf>) -> Compression {
+        compress.unwrap_or_else(|| match output {
+            Some(output) => Compression::from_extension(output),
+            None => Compression::None,
+        })
+    }
+
+    // The stream must be finished with `Encoder::finish` to write the end of it.
+    pub fn wrap_writer(&self, writer: impl Write + Send + 'static) -> Encoder {
+        let writer: Box<dyn Write + Send> = Box::new(writer);
+        match self {
+            Compression::None => Encoder::None(writer),
+            Compression::Gzip => {
+                Encoder::Gzip(GzEncoder::new(writer, flate2::Compression::default()))
+            }
+            Compression::Zstd => {
+                Encoder::Zstd(zstd::Encoder::new(writer, 0).expect("Error creating compressor"))
+            }
+        }
+    }
+}
+
+// Output written with one of the compressions. Dropping a compressed stream would finish it
+// without reporting errors, so it's kept as its concrete type to be finished explicitly.
+pub enum Encoder {
+    None(Box<dyn Write + Send>),
+    Gzip(GzEncoder<Box<dyn Write + Send>>),
+    Zstd(zstd::Encoder<'static, Box<dyn Write + Send>>),
+}
+
+impl Encoder {
+    // Write the end of the compressed stream, and flush the output beneath it.
+    pub fn finish(self) -> io::Result<()> {
+        let mut writer = match self {
+            Encoder::None(writer) => writer,
+            Encoder::Gzip(encoder) => encoder.finish()?,
+            Encoder::Zstd(encoder) => encoder.finish()?,
+        };
+        writer.flush()
+    }
+}
+
+impl Write for Encoder {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Encoder::None(writer) => writer.write(buf),
+            Encoder::Gzip(encoder) => encoder.write(buf),
+            Encoder::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Encoder::None(writer) => writer.flush(),
+            Encoder::Gzip(encoder) => encoder.flush(),
+            Encoder::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
+
+pub fn is_compressed(path: &Path) -> io::Result<bool> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let header = reader.fill_buf()?;
+    Ok(header.starts_with(&ZSTD_MAGIC) || header.starts_with(&GZIP_MAGIC))
+}
+
+// Open a file, decompressing it if it starts with a gzip or zstd header.
+pub fn open_decompressed(path: &Path) -> io::Result<Box<dyn BufRead>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let header = reader.fill_buf()?;
+    if header.starts_with(&ZSTD_MAGIC) {
+        Ok(Box::new(BufReader::new(zstd::Decoder::with_buffer(
+            reader,
+        )?)))
+    } else if header.starts_with(&GZIP_MAGIC) {
+        Ok(Box::new(BufReader::new(
+            flate2::bufread::MultiGzDecoder::new(reader),
+        )))
+    } else {
+        Ok(Box::new(reader))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    struct Full;
+
+    impl Write for Full {
+        fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+            Err(io::Error::new(io::ErrorKind::StorageFull, "full"))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn finishing_writes_the_end_of_the_stream_and_reports_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        for compression in [Compression::Gzip, Compression::Zstd] {
+            let path = dir.path().join(compression.to_string());
+            let mut encoder = compression.wrap_writer(File::create(&path).unwrap());
+            encoder.write_all(b"line\n").unwrap();
+            encoder.finish().unwrap();
+            let mut contents = String::new();
+            open_decompressed(&path)
+                .unwrap()
+                .read_to_string(&mut contents)
+                .unwrap();
+            assert_eq!(contents, "line\n");
+
+            // Errors writing the end of the stream are returned rather than lost on drop.
+            let mut encoder = compression.wrap_writer(Full);
+            let written = encoder.write_all(b"line\n").and_then(|_| encoder.finish());
+            assert!(written.is_err(), "{}", compression);
+        }
+    }
+}
@@ -1,7 +1,41 @@
 use crate::walker::MetadataWithSize;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+const SECONDS_PER_DAY: u64 = 60 * 60 * 24;
+
+// The maximum number of distinct extensions recorded for a single directory. Anything beyond
+// this is counted under OTHER_EXTENSIONS.
+const MAX_EXTENSIONS: usize = 64;
+pub const NO_EXTENSION: &str = "(none)";
+pub const OTHER_EXTENSIONS: &str = "(other)";
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct BreakdownStat {
+    pub file_count: u64,
+    pub total_size: u64,
+}
+
+pub type BreakdownStats<K> = BTreeMap<K, BreakdownStat>;
+
+// Files that were last modified or accessed at least a given number of days before the scan
+// started.
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct AgeStat {
+    pub modified: BreakdownStat,
+    pub accessed: BreakdownStat,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct FileStat {
+    pub path: PathBuf,
+    pub size: u64,
+}
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct DirectoryStat {
@@ -13,12 +47,110 @@ pub struct DirectoryStat {
     pub latest_created: Option<DateTime<Utc>>,
     pub latest_accessed: Option<DateTime<Utc>>,
     pub latest_modified: Option<DateTime<Utc>>,
+
+    #[serde(default)]
+    pub earliest_created: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub earliest_accessed: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub earliest_modified: Option<DateTime<Utc>>,
+
+    // The modification time of the directory itself and the number of entries directly within
+    // it, used to detect unchanged directories when scanning with --baseline.
+    #[serde(default)]
+    pub directory_modified: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub child_count: u64,
+    // The totals for files directly within this directory were reused from the baseline scan.
+    #[serde(default)]
+    pub reused: bool,
+
+    // Bytes not included in total_size, because the file was a hard link to one already counted.
+    #[serde(default)]
+    pub deduplicated_size: u64,
+
+    // Totals for the directory and everything below it. These are only complete once the
+    // whole subtree has been walked, and are missing from files produced by older versions.
+    #[serde(default)]
+    pub recursive_total_size: u64,
+    #[serde(default)]
+    pub recursive_file_count: u64,
+    #[serde(default)]
+    pub recursive_largest_file_size: u64,
+    #[serde(default)]
+    pub recursive_latest_created: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub recursive_latest_accessed: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub recursive_latest_modified: Option<DateTime<Utc>>,
+
+    // The directory is a mount point for a different filesystem, and its contents were skipped.
+    #[serde(default)]
+    pub skipped_mount_point: bool,
+
+    // The number of entries directly within this directory that couldn't be read, including
+    // the directory's own contents.
+    #[serde(default)]
+    pub errors: u64,
+    // Something in this directory or beneath it couldn't be read, so the totals are a lower
+    // bound.
+    #[serde(default)]
+    pub incomplete: bool,
+
+    // Only present when scanning with --extensions
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "crate::formats::deserialize_nested"
+    )]
+    pub extensions: Option<BreakdownStats<String>>,
+
+    // Only present when scanning with --owners, keyed by uid and gid
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "crate::formats::deserialize_nested"
+    )]
+    pub owners: Option<BreakdownStats<u32>>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "crate::formats::deserialize_nested"
+    )]
+    pub groups: Option<BreakdownStats<u32>>,
+
+    // Only present when scanning with --histogram. See histogram_bucket for the bucket sizes.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "crate::formats::deserialize_nested"
+    )]
+    pub size_histogram: Option<Vec<u64>>,
+
+    // Only present when scanning with --age-buckets, keyed by the number of days.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "crate::formats::deserialize_nested"
+    )]
+    pub age_buckets: Option<BTreeMap<u32, AgeStat>>,
+
+    // Only present when scanning with --largest-files, ordered from largest to smallest.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "crate::formats::deserialize_nested"
+    )]
+    pub largest_files: Option<Vec<FileStat>>,
 }
 
 impl DirectoryStat {
     pub fn from_metadata(path: PathBuf, metadata: &MetadataWithSize) -> DirectoryStat {
         let file_count = if metadata.is_dir { 0 } else { 1 };
         let total_size = metadata.size;
+        let created = metadata.metadata.created().map(|f| f.into()).ok();
+        let accessed = metadata.metadata.accessed().map(|f| f.into()).ok();
+        let modified = metadata.metadata.modified().map(|f| f.into()).ok();
 
         DirectoryStat {
             total_size,
@@ -26,63 +158,337 @@ impl DirectoryStat {
             largest_file_size: total_size,
             path,
 
-            latest_created: metadata.metadata.created().map(|f| f.into()).ok(),
-            latest_accessed: metadata.metadata.accessed().map(|f| f.into()).ok(),
-            latest_modified: metadata.metadata.modified().map(|f| f.into()).ok(),
+            latest_created: created,
+            latest_accessed: accessed,
+            latest_modified: modified,
+
+            earliest_created: created,
+            earliest_accessed: accessed,
+            earliest_modified: modified,
+
+            directory_modified: if metadata.is_dir { modified } else { None },
+            child_count: 0,
+            reused: false,
+
+            deduplicated_size: 0,
+
+            recursive_total_size: 0,
+            recursive_file_count: 0,
+            recursive_largest_file_size: 0,
+            recursive_latest_created: None,
+            recursive_latest_accessed: None,
+            recursive_latest_modified: None,
+
+            skipped_mount_point: metadata.skipped_mount_point,
+            errors: 0,
+            incomplete: false,
+
+            extensions: None,
+            owners: None,
+            groups: None,
+            size_histogram: None,
+            age_buckets: None,
+            largest_files: None,
         }
     }
 
-    pub fn merge(&mut self, other: &DirectoryStat) {
+    // Add the totals of `other` to this directory, other than the recursive totals. Those are
+    // merged with `merge_recursive`, as when folding a directory's descendants into it their
+    // recursive totals would be counted more than once. The lists of largest files are kept to
+    // `largest_files` long, the number the scan recorded, or combined in full if it isn't known.
+    pub fn merge(&mut self, other: &DirectoryStat, largest_files: Option<usize>) {
         self.total_size += other.total_size;
         self.file_count += other.file_count;
+        self.deduplicated_size += other.deduplicated_size;
+        self.child_count += other.child_count;
+        self.reused |= other.reused;
+        self.errors += other.errors;
+        self.incomplete |= other.incomplete;
         if other.largest_file_size > self.largest_file_size {
             self.largest_file_size = other.largest_file_size;
         }
-        if let Some(created) = other.latest_created {
-            self.update_latest_created(created);
+        update_latest(&mut self.latest_created, other.latest_created);
+        update_latest(&mut self.latest_accessed, other.latest_accessed);
+        update_latest(&mut self.latest_modified, other.latest_modified);
+        update_earliest(&mut self.earliest_created, other.earliest_created);
+        update_earliest(&mut self.earliest_accessed, other.earliest_accessed);
+        update_earliest(&mut self.earliest_modified, other.earliest_modified);
+        if let Some(other_extensions) = &other.extensions {
+            let extensions = self.extensions.get_or_insert_with(Default::default);
+            for (extension, stat) in other_extensions {
+                add_extension(extensions, extension, stat.file_count, stat.total_size);
+            }
         }
-        if let Some(accessed) = other.latest_accessed {
-            self.update_latest_accessed(accessed);
+        merge_breakdown(&mut self.owners, &other.owners);
+        merge_breakdown(&mut self.groups, &other.groups);
+        if let Some(other_histogram) = &other.size_histogram {
+            let histogram = self.size_histogram.get_or_insert_with(Vec::new);
+            if histogram.len() < other_histogram.len() {
+                histogram.resize(other_histogram.len(), 0);
+            }
+            for (bucket, count) in other_histogram.iter().enumerate() {
+                histogram[bucket] += count;
+            }
         }
-        if let Some(modified) = other.latest_modified {
-            self.update_latest_modified(modified);
+        if let Some(other_buckets) = &other.age_buckets {
+            let buckets = self.age_buckets.get_or_insert_with(Default::default);
+            for (days, other_stat) in other_buckets {
+                let stat = buckets.entry(*days).or_default();
+                stat.modified.file_count += other_stat.modified.file_count;
+                stat.modified.total_size += other_stat.modified.total_size;
+                stat.accessed.file_count += other_stat.accessed.file_count;
+                stat.accessed.total_size += other_stat.accessed.total_size;
+            }
+        }
+        if let Some(other_largest_files) = &other.largest_files {
+            let limit = largest_files.unwrap_or(usize::MAX);
+            let largest_files = self.largest_files.get_or_insert_with(Vec::new);
+            for file in other_largest_files {
+                add_largest_file(largest_files, limit, file.size, || file.path.clone());
+            }
         }
     }
 
-    // Please oh god tell me this can be generalized somehow.
-    pub fn update_latest_created(&mut self, created: DateTime<Utc>) {
-        match self.latest_created {
-            None => {
-                self.latest_created.replace(created);
-            }
-            Some(dt) if dt < created => {
-                self.latest_created.replace(created);
-            }
-            _ => {}
+    // Does nothing unless the extensions map has been enabled for this directory.
+    pub fn add_file_extension(&mut self, file_name: &OsStr, size: u64) {
+        if let Some(extensions) = &mut self.extensions {
+            let extension = Path::new(file_name)
+                .extension()
+                .map(|e| e.to_string_lossy().to_lowercase());
+            let extension = extension.as_deref().unwrap_or(NO_EXTENSION);
+            add_extension(extensions, extension, 1, size);
         }
     }
 
-    pub fn update_latest_accessed(&mut self, accessed: DateTime<Utc>) {
-        match self.latest_accessed {
-            None => {
-                self.latest_accessed.replace(accessed);
-            }
-            Some(dt) if dt < accessed => {
-                self.latest_accessed.replace(accessed);
+    // Does nothing unless the histogram has been enabled for this directory.
+    pub fn add_file_to_histogram(&mut self, size: u64) {
+        if let Some(histogram) = &mut self.size_histogram {
+            let bucket = histogram_bucket(size);
+            if histogram.len() <= bucket {
+                histogram.resize(bucket + 1, 0);
             }
-            _ => {}
+            histogram[bucket] += 1;
         }
     }
 
-    pub fn update_latest_modified(&mut self, modified: DateTime<Utc>) {
-        match self.latest_modified {
-            None => {
-                self.latest_modified.replace(modified);
-            }
-            Some(dt) if dt < modified => {
-                self.latest_modified.replace(modified);
+    // Does nothing unless age buckets have been enabled for this directory.
+    pub fn add_file_age(&mut self, metadata: &std::fs::Metadata, size: u64, now: SystemTime) {
+        if let Some(buckets) = &mut self.age_buckets {
+            let age_in_days = |time: io::Result<SystemTime>| {
+                time.ok()
+                    .and_then(|time| now.duration_since(time).ok())
+                    .map(|age| age.as_secs() / SECONDS_PER_DAY)
+            };
+            let modified_days = age_in_days(metadata.modified());
+            let accessed_days = age_in_days(metadata.accessed());
+            for (days, stat) in buckets.iter_mut() {
+                if modified_days.is_some_and(|age| age >= *days as u64) {
+                    stat.modified.file_count += 1;
+                    stat.modified.total_size += size;
+                }
+                if accessed_days.is_some_and(|age| age >= *days as u64) {
+                    stat.accessed.file_count += 1;
+                    stat.accessed.total_size += size;
+                }
             }
-            _ => {}
         }
     }
+
+    // Does nothing unless the owners and groups maps have been enabled for this directory.
+    #[cfg(target_family = "unix")]
+    pub fn add_file_owner(&mut self, metadata: &std::fs::Metadata, size: u64) {
+        use std::os::unix::fs::MetadataExt;
+        if let Some(owners) = &mut self.owners {
+            add_to_breakdown(owners, metadata.uid(), 1, size);
+        }
+        if let Some(groups) = &mut self.groups {
+            add_to_breakdown(groups, metadata.gid(), 1, size);
+        }
+    }
+
+    #[cfg(not(target_family = "unix"))]
+    pub fn add_file_owner(&mut self, _metadata: &std::fs::Metadata, _size: u64) {}
+
+    /// Replace everything other than the recursive totals with the statistics from a previous
+    /// scan of this directory.
+    pub fn reuse(&mut self, previous: &DirectoryStat) {
+        let mut reused = previous.clone();
+        reused.recursive_total_size = self.recursive_total_size;
+        reused.recursive_file_count = self.recursive_file_count;
+        reused.recursive_largest_file_size = self.recursive_largest_file_size;
+        reused.recursive_latest_created = self.recursive_latest_created;
+        reused.recursive_latest_accessed = self.recursive_latest_accessed;
+        reused.recursive_latest_modified = self.recursive_latest_modified;
+        // Whether anything beneath is incomplete depends on this scan.
+        reused.incomplete = self.incomplete;
+        reused.reused = true;
+        *self = reused;
+    }
+
+    /// Add the recursive totals of `other` to the recursive totals of this directory.
+    pub fn merge_recursive(&mut self, other: &DirectoryStat) {
+        self.recursive_total_size += other.recursive_total_size;
+        self.recursive_file_count += other.recursive_file_count;
+        self.incomplete |= other.incomplete;
+        if other.recursive_largest_file_size > self.recursive_largest_file_size {
+            self.recursive_largest_file_size = other.recursive_largest_file_size;
+        }
+        update_latest(
+            &mut self.recursive_latest_created,
+            other.recursive_latest_created,
+        );
+        update_latest(
+            &mut self.recursive_latest_accessed,
+            other.recursive_latest_accessed,
+        );
+        update_latest(
+            &mut self.recursive_latest_modified,
+            other.recursive_latest_modified,
+        );
+    }
+
+    /// Called once every file directly within this directory has been seen, adding them to the
+    /// recursive totals.
+    pub fn finish_recursive(&mut self) {
+        self.recursive_total_size += self.total_size;
+        self.recursive_file_count += self.file_count;
+        self.incomplete |= self.errors > 0;
+        if self.largest_file_size > self.recursive_largest_file_size {
+            self.recursive_largest_file_size = self.largest_file_size;
+        }
+        update_latest(&mut self.recursive_latest_created, self.latest_created);
+        update_latest(&mut self.recursive_latest_accessed, self.latest_accessed);
+        update_latest(&mut self.recursive_latest_modified, self.latest_modified);
+    }
+
+    // Update the latest and earliest timestamps with those of a file in this directory.
+    pub fn update_timestamps(&mut self, metadata: &std::fs::Metadata) {
+        let created = metadata.created().map(|f| f.into()).ok();
+        let accessed = metadata.accessed().map(|f| f.into()).ok();
+        let modified = metadata.modified().map(|f| f.into()).ok();
+        update_latest(&mut self.latest_created, created);
+        update_latest(&mut self.latest_accessed, accessed);
+        update_latest(&mut self.latest_modified, modified);
+        update_earliest(&mut self.earliest_created, created);
+        update_earliest(&mut self.earliest_accessed, accessed);
+        update_earliest(&mut self.earliest_modified, modified);
+    }
+}
+
+/// Add a file to a list of at most `limit` files, ordered from largest to smallest. The path is
+/// only built if the file is large enough to be included.
+pub fn add_largest_file(
+    largest_files: &mut Vec<FileStat>,
+    limit: usize,
+    size: u64,
+    path: impl FnOnce() -> PathBuf,
+) {
+    if largest_files.len() >= limit && largest_files.last().is_none_or(|f| f.size >= size) {
+        return;
+    }
+    let index = largest_files.partition_point(|f| f.size >= size);
+    largest_files.insert(index, FileStat { path: path(), size });
+    largest_files.truncate(limit);
+}
+
+/// Files are bucketed by the base 2 logarithm of their size. Bucket 0 contains empty files, and
+/// bucket N contains files with a size of at least 2^(N-1) and less than 2^N bytes. Histograms
+/// only contain buckets up to the largest non-empty one.
+pub fn histogram_bucket(size: u64) -> usize {
+    (64 - size.leading_zeros()) as usize
+}
+
+fn add_extension(
+    extensions: &mut BreakdownStats<String>,
+    extension: &str,
+    file_count: u64,
+    size: u64,
+) {
+    let key = if extensions.len() < MAX_EXTENSIONS || extensions.contains_key(extension) {
+        extension
+    } else {
+        OTHER_EXTENSIONS
+    };
+    add_to_breakdown(extensions, key.to_string(), file_count, size);
+}
+
+fn add_to_breakdown<K: Ord>(breakdown: &mut BreakdownStats<K>, key: K, file_count: u64, size: u64) {
+    let stat = breakdown.entry(key).or_default();
+    stat.file_count += file_count;
+    stat.total_size += size;
+}
+
+fn merge_breakdown<K: Ord + Clone>(
+    breakdown: &mut Option<BreakdownStats<K>>,
+    other: &Option<BreakdownStats<K>>,
+) {
+    if let Some(other) = other {
+        let breakdown = breakdown.get_or_insert_with(Default::default);
+        for (key, stat) in other {
+            add_to_breakdown(breakdown, key.clone(), stat.file_count, stat.total_size);
+        }
+    }
+}
+
+fn update_latest(current: &mut Option<DateTime<Utc>>, other: Option<DateTime<Utc>>) {
+    match (*current, other) {
+        (_, None) => {}
+        (Some(dt), Some(other)) if dt >= other => {}
+        (_, Some(other)) => {
+            current.replace(other);
+        }
+    }
+}
+
+fn update_earliest(current: &mut Option<DateTime<Utc>>, other: Option<DateTime<Utc>>) {
+    match (*current, other) {
+        (_, None) => {}
+        (Some(dt), Some(other)) if dt <= other => {}
+        (_, Some(other)) => {
+            current.replace(other);
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    // A directory with these totals for the files directly within it.
+    pub fn stat(path: &str, total_size: u64, file_count: u64) -> DirectoryStat {
+        serde_json::from_value(serde_json::json!({
+            "total_size": total_size,
+            "file_count": file_count,
+            "largest_file_size": total_size,
+            "path": path,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn finishing_a_directory_rolls_up_its_subdirectories() {
+        let mut child = stat("/a/b", 20, 2);
+        child.finish_recursive();
+        let mut parent = stat("/a", 10, 1);
+        parent.merge_recursive(&child);
+        parent.finish_recursive();
+        assert_eq!(parent.total_size, 10);
+        assert_eq!(parent.recursive_total_size, 30);
+        assert_eq!(parent.recursive_file_count, 3);
+        assert_eq!(parent.recursive_largest_file_size, 20);
+    }
+
+    #[test]
+    fn merge_leaves_recursive_totals_alone() {
+        let mut child = stat("/a/b", 20, 2);
+        child.finish_recursive();
+        let mut grandchild = stat("/a/b/c", 5, 1);
+        grandchild.finish_recursive();
+        // Folding descendants into a prefix, as parse does, only sums their own files.
+        let mut prefix = child.clone();
+        prefix.merge(&grandchild, None);
+        assert_eq!(prefix.total_size, 25);
+        assert_eq!(prefix.file_count, 3);
+        assert_eq!(prefix.recursive_total_size, 20);
+    }
 }
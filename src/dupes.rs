@@ -0,0 +1,196 @@
+use crate::error_log::{ErrorLog, Operation};
+use crate::progress::WalkProgress;
+use crate::walker::Walker;
+use console::style;
+use indicatif::HumanBytes;
+use prettytable::table;
+use rayon::prelude::*;
+use rayon::ThreadPool;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+// The number of bytes hashed from both the start and the end of a file, when looking for
+// files that could be duplicates before hashing their whole contents.
+const PARTIAL_HASH_SIZE: u64 = 4096;
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct DuplicateGroup {
+    pub size: u64,
+    pub count: u64,
+    // The space that would be saved by keeping only one copy of the file
+    pub wasted_size: u64,
+    pub hash: String,
+    pub paths: Vec<PathBuf>,
+}
+
+pub struct Duplicates {
+    pub groups: Vec<DuplicateGroup>,
+}
+
+impl Duplicates {
+    // Files are grouped by their size, then by a hash of their first and last blocks, then finally
+    // by a hash of their contents. Each stage only considers files that share a group with at
+    // least one other file in the previous stage. Anything that can't be read is recorded in
+    // `error_log` and skipped.
+    pub fn find(
+        root: &PathBuf,
+        walker: Walker,
+        thread_pool: &ThreadPool,
+        error_log: &ErrorLog,
+    ) -> Duplicates {
+        let mut walk_progress = WalkProgress::new(vec![root.clone()]);
+        let progress_bar = walk_progress.create_progress_bar();
+
+        let mut seen_hard_links = HashSet::new();
+        let mut by_size: HashMap<u64, Vec<PathBuf>> = HashMap::new();
+
+        for dir in &mut walker.walk_dir(root) {
+            walk_progress.record_progress(&dir);
+            if walk_progress.should_update() {
+                walk_progress.update(&progress_bar);
+            }
+
+            let dir_entry = match dir {
+                Ok(dir_entry) => dir_entry,
+                Err(error) => {
+                    let directory = error.path().unwrap_or(root).to_path_buf();
+                    error_log.record_walk_error(&directory, Operation::ReadDir, &error);
+                    continue;
+                }
+            };
+            if let Some(error) = &dir_entry.read_children_error {
+                error_log.record_walk_error(&dir_entry.path(), Operation::ReadDir, error);
+            }
+            if !dir_entry.file_type.is_file() {
+                continue;
+            }
+
+            if let Some(metadata) = &dir_entry.client_state {
+                // Empty files and other links to the same file don't waste any space.
+                if metadata.size == 0 || metadata.is_duplicate_hard_link(&mut seen_hard_links) {
+                    continue;
+                }
+                by_size
+                    .entry(metadata.size)
+                    .or_default()
+                    .push(dir_entry.path());
+            }
+        }
+
+        progress_bar.finish_and_clear();
+
+        let candidates = by_size
+            .into_iter()
+            .filter(|(_size, paths)| paths.len() > 1)
+            .flat_map(|(size, paths)| paths.into_iter().map(move |path| (size, path)))
+            .collect();
+        // The partial hash of a small file already covers all of it, so its group is final.
+        let (small, large): (Vec<_>, Vec<_>) =
+            group_by_hash(thread_pool, candidates, partial_hash, error_log)
+                .into_iter()
+                .partition(|((size, _hash), _paths)| *size <= PARTIAL_HASH_SIZE * 2);
+        let candidates = large
+            .into_iter()
+            .flat_map(|((size, _hash), paths)| paths.into_iter().map(move |path| (size, path)))
+            .collect();
+        let full = group_by_hash(thread_pool, candidates, full_hash, error_log);
+
+        error_log.flush().expect("Error writing error log");
+        walk_progress.set_error_kinds(error_log.counts());
+        eprintln!("{}", walk_progress);
+
+        let mut groups: Vec<_> = small
+            .into_iter()
+            .chain(full)
+            .map(|((size, hash), mut paths)| {
+                paths.sort();
+                let count = paths.len() as u64;
+                DuplicateGroup {
+                    size,
+                    count,
+                    wasted_size: size * (count - 1),
+                    hash: hash.to_hex().to_string(),
+                    paths,
+                }
+            })
+            .collect();
+        groups.sort_by(|a, b| {
+            b.wasted_size
+                .cmp(&a.wasted_size)
+                .then_with(|| a.paths.cmp(&b.paths))
+        });
+
+        Duplicates { groups }
+    }
+}
+
+impl fmt::Display for Duplicates {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let files: u64 = self.groups.iter().map(|g| g.count).sum();
+        let wasted_size: u64 = self.groups.iter().map(|g| g.wasted_size).sum();
+        let mut table = table!(
+            ["Duplicate Groups", style(self.groups.len()).green()],
+            ["Duplicate Files", style(files).green()],
+            ["Wasted Size", style(HumanBytes(wasted_size)).red()]
+        );
+        table.set_format(*prettytable::format::consts::FORMAT_CLEAN);
+        write!(f, "{}", table)
+    }
+}
+
+// Hash every file in parallel, returning the groups of files that have the same size and hash.
+// Files that can't be read are recorded in `error_log` and left out.
+fn group_by_hash(
+    thread_pool: &ThreadPool,
+    files: Vec<(u64, PathBuf)>,
+    hash: fn(&Path, u64) -> io::Result<blake3::Hash>,
+    error_log: &ErrorLog,
+) -> HashMap<(u64, blake3::Hash), Vec<PathBuf>> {
+    let hashed: Vec<_> = thread_pool.install(|| {
+        files
+            .into_par_iter()
+            .filter_map(|(size, path)| match hash(&path, size) {
+                Ok(file_hash) => Some(((size, file_hash), path)),
+                Err(error) => {
+                    let directory = path.parent().unwrap_or(&path);
+                    error_log.record(directory, &path, Operation::Read, &error);
+                    None
+                }
+            })
+            .collect()
+    });
+
+    let mut groups: HashMap<_, Vec<PathBuf>> = HashMap::new();
+    for (key, path) in hashed {
+        groups.entry(key).or_default().push(path);
+    }
+    groups.retain(|_key, paths| paths.len() > 1);
+    groups
+}
+
+fn partial_hash(path: &Path, size: u64) -> io::Result<blake3::Hash> {
+    if size <= PARTIAL_HASH_SIZE * 2 {
+        // The first and last blocks cover the whole file, so this is also its full hash.
+        return full_hash(path, size);
+    }
+    let mut file = File::open(path)?;
+    let mut hasher = blake3::Hasher::new();
+    let mut buffer = vec![0; PARTIAL_HASH_SIZE as usize];
+    file.read_exact(&mut buffer)?;
+    hasher.update(&buffer);
+    file.seek(SeekFrom::End(-(PARTIAL_HASH_SIZE as i64)))?;
+    file.read_exact(&mut buffer)?;
+    hasher.update(&buffer);
+    Ok(hasher.finalize())
+}
+
+fn full_hash(path: &Path, _size: u64) -> io::Result<blake3::Hash> {
+    let mut file = File::open(path)?;
+    let mut hasher = blake3::Hasher::new();
+    io::copy(&mut file, &mut hasher)?;
+    Ok(hasher.finalize())
+}
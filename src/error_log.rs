@@ -0,0 +1,184 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+#[derive(Serialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum Operation {
+    ReadDir,
+    Metadata,
+    SizeOnDisk,
+    // Reading a file's contents, to hash it when finding duplicates.
+    Read,
+}
+
+#[derive(Serialize)]
+struct ErrorRecord<'a> {
+    path: &'a Path,
+    operation: Operation,
+    kind: String,
+    errno: Option<i32>,
+    message: String,
+}
+
+// Errors encountered during a scan, counted by kind and optionally written to a file with one
+// JSON record per line. Errors are recorded from the walker's threads as well as the main thread.
+pub struct ErrorLog {
+    output: Option<Mutex<BufWriter<File>>>,
+    counts: Mutex<BTreeMap<String, u64>>,
+    // The number of errors within each directory that hasn't been finished yet.
+    directories: Mutex<BTreeMap<PathBuf, u64>>,
+}
+
+impl ErrorLog {
+    pub fn new(path: Option<PathBuf>) -> io::Result<ErrorLog> {
+        let output = match path {
+            Some(path) => Some(Mutex::new(BufWriter::new(File::create(path)?))),
+            None => None,
+        };
+        Ok(ErrorLog {
+            output,
+            counts: Mutex::new(BTreeMap::new()),
+            directories: Mutex::new(BTreeMap::new()),
+        })
+    }
+
+    // `directory` is the directory whose totals are missing whatever couldn't be read.
+    pub fn record(&self, directory: &Path, path: &Path, operation: Operation, error: &io::Error) {
+        let kind = format!("{:?}", error.kind());
+        self.write(
+            directory,
+            path,
+            operation,
+            kind,
+            error.raw_os_error(),
+            error.to_string(),
+        );
+    }
+
+    // jwalk errors that aren't IO errors are loops found when following symlinks.
+    pub fn record_walk_error(&self, directory: &Path, operation: Operation, error: &jwalk::Error) {
+        let path = error.path().unwrap_or(directory);
+        match error.io_error() {
+            Some(io_error) => self.record(directory, path, operation, io_error),
+            None => {
+                let kind = "Loop".to_string();
+                self.write(directory, path, operation, kind, None, error.to_string())
+            }
+        }
+    }
+
+    fn write(
+        &self,
+        directory: &Path,
+        path: &Path,
+        operation: Operation,
+        kind: String,
+        errno: Option<i32>,
+        message: String,
+    ) {
+        if let Some(output) = &self.output {
+            let record = ErrorRecord {
+                path,
+                operation,
+                kind: kind.clone(),
+                errno,
+                message,
+            };
+            let mut output = output.lock().unwrap();
+            serde_json::to_writer(&mut *output, &record).expect("Error writing error log");
+            writeln!(output).expect("Error writing error log");
+        }
+        *self.counts.lock().unwrap().entry(kind).or_insert(0) += 1;
+        *self
+            .directories
+            .lock()
+            .unwrap()
+            .entry(directory.to_path_buf())
+            .or_insert(0) += 1;
+    }
+
+    // Remove and count the errors within a directory and any directories beneath it, which
+    // haven't already been taken.
+    pub fn take_errors(&self, directory: &Path) -> u64 {
+        let mut directories = self.directories.lock().unwrap();
+        let beneath: Vec<PathBuf> = directories
+            .range(directory.to_path_buf()..)
+            .take_while(|(path, _)| path.starts_with(directory))
+            .map(|(path, _)| path.clone())
+            .collect();
+        beneath
+            .iter()
+            .map(|path| directories.remove(path).unwrap())
+            .sum()
+    }
+
+    // The number of errors of each kind, such as `PermissionDenied`.
+    pub fn counts(&self) -> BTreeMap<String, u64> {
+        self.counts.lock().unwrap().clone()
+    }
+
+    pub fn flush(&self) -> io::Result<()> {
+        match &self.output {
+            Some(output) => output.lock().unwrap().flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn errors_are_logged_and_counted_by_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("errors.json");
+        let error_log = ErrorLog::new(Some(path.clone())).unwrap();
+        let denied = io::Error::from_raw_os_error(13);
+        error_log.record(
+            Path::new("/a/b"),
+            Path::new("/a/b"),
+            Operation::ReadDir,
+            &denied,
+        );
+        let not_found = io::Error::from(io::ErrorKind::NotFound);
+        error_log.record(
+            Path::new("/a"),
+            Path::new("/a/f"),
+            Operation::Metadata,
+            &not_found,
+        );
+        error_log.flush().unwrap();
+
+        let logged: Vec<serde_json::Value> = std::fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(logged.len(), 2);
+        assert_eq!(logged[0]["path"], "/a/b");
+        assert_eq!(logged[0]["operation"], "read_dir");
+        assert_eq!(logged[0]["kind"], "PermissionDenied");
+        assert_eq!(logged[0]["errno"], 13);
+        assert_eq!(logged[1]["path"], "/a/f");
+        assert_eq!(logged[1]["operation"], "metadata");
+        assert_eq!(logged[1]["kind"], "NotFound");
+        assert!(logged[1]["errno"].is_null());
+
+        let counts: Vec<_> = error_log.counts().into_iter().collect();
+        assert_eq!(
+            counts,
+            [
+                ("NotFound".to_string(), 1),
+                ("PermissionDenied".to_string(), 1)
+            ]
+        );
+        // Errors beneath a directory are taken along with its own, and only once.
+        assert_eq!(error_log.take_errors(Path::new("/a")), 2);
+        assert_eq!(error_log.take_errors(Path::new("/a/b")), 0);
+    }
+}
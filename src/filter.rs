@@ -0,0 +1,130 @@
+use globset::{Glob, GlobBuilder, GlobSet, GlobSetBuilder};
+use regex::{Regex, RegexSet};
+use std::path::Path;
+
+// `*` and `?` don't match `/`, so a pattern such as `/data/*/scratch` only matches a single
+// directory between the two. `**` matches any number of directories.
+pub fn parse_glob(pattern: &str) -> Result<Glob, globset::Error> {
+    GlobBuilder::new(pattern).literal_separator(true).build()
+}
+
+#[derive(Clone)]
+pub struct Filter {
+    include: Patterns,
+    exclude: Patterns,
+}
+
+impl Filter {
+    pub fn new(
+        include: Vec<Glob>,
+        exclude: Vec<Glob>,
+        include_regex: Vec<Regex>,
+        exclude_regex: Vec<Regex>,
+    ) -> Filter {
+        Filter {
+            include: Patterns::new(include, include_regex),
+            exclude: Patterns::new(exclude, exclude_regex),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty()
+    }
+
+    // Directories are only ever pruned by exclude patterns, as files further down may still
+    // match an include pattern.
+    pub fn allows_dir(&self, path: &Path) -> bool {
+        !self.exclude.is_match(path)
+    }
+
+    pub fn allows_file(&self, path: &Path) -> bool {
+        if self.exclude.is_match(path) {
+            return false;
+        }
+        self.include.is_empty() || self.include.is_match(path)
+    }
+}
+
+impl Default for Filter {
+    fn default() -> Filter {
+        Filter::new(vec![], vec![], vec![], vec![])
+    }
+}
+
+#[derive(Clone)]
+struct Patterns {
+    globs: GlobSet,
+    regexes: RegexSet,
+}
+
+impl Patterns {
+    fn new(globs: Vec<Glob>, regexes: Vec<Regex>) -> Patterns {
+        let mut builder = GlobSetBuilder::new();
+        for glob in globs {
+            builder.add(glob);
+        }
+        Patterns {
+            globs: builder.build().expect("Error building glob set"),
+            regexes: RegexSet::new(regexes.iter().map(|r| r.as_str()))
+                .expect("Error building regex set"),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.globs.is_empty() && self.regexes.is_empty()
+    }
+
+    fn is_match(&self, path: &Path) -> bool {
+        // Globs are matched against both the file name and the full path, so both `*.log` and
+        // `/data/*/scratch` work as expected. Regexes are only matched against the full path.
+        if !self.globs.is_empty() {
+            let name_matches = path
+                .file_name()
+                .is_some_and(|name| self.globs.is_match(name));
+            if name_matches || self.globs.is_match(path) {
+                return true;
+            }
+        }
+        !self.regexes.is_empty() && self.regexes.is_match(&path.to_string_lossy())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(include: &[&str], exclude: &[&str], exclude_regex: &[&str]) -> Filter {
+        let globs = |patterns: &[&str]| patterns.iter().map(|p| parse_glob(p).unwrap()).collect();
+        let regexes = exclude_regex
+            .iter()
+            .map(|r| Regex::new(r).unwrap())
+            .collect();
+        Filter::new(globs(include), globs(exclude), vec![], regexes)
+    }
+
+    #[test]
+    fn globs_match_file_names_and_full_paths() {
+        let filter = filter(&["*.log"], &["node_modules", "/data/*/scratch"], &[]);
+        assert!(filter.allows_file(Path::new("/data/a/app.log")));
+        assert!(!filter.allows_file(Path::new("/data/a/app.txt")));
+        assert!(!filter.allows_dir(Path::new("/src/node_modules")));
+        assert!(!filter.allows_dir(Path::new("/data/a/scratch")));
+        // `*` doesn't match across directories.
+        assert!(filter.allows_dir(Path::new("/data/a/b/scratch")));
+    }
+
+    #[test]
+    fn include_patterns_only_apply_to_files() {
+        let filter = filter(&["*.log"], &[], &[]);
+        assert!(filter.allows_dir(Path::new("/data/a")));
+        assert!(!filter.allows_file(Path::new("/data/a")));
+    }
+
+    #[test]
+    fn exclude_regexes_match_the_full_path() {
+        let filter = filter(&[], &[], &["^/data/[0-9]+/"]);
+        assert!(!filter.allows_file(Path::new("/data/123/f")));
+        assert!(filter.allows_file(Path::new("/data/a123/f")));
+        assert!(filter.allows_dir(Path::new("/data/123")));
+    }
+}
@@ -1,80 +1,566 @@
 use std::fs::File;
-use std::io;
+use std::io::{self, BufRead};
 
+use crate::columns::{self, Record};
+use crate::compression::{self, Compression, Encoder};
 use crate::directory_stat::DirectoryStat;
+use crate::metadata::{self, MetadataObserver, ScanMetadata};
+use crate::parquet_format::{self, ParquetWriter};
+use crate::sqlite_format;
+use serde::de::value::{MapAccessDeserializer, SeqAccessDeserializer};
+use serde::de::{self, DeserializeOwned, MapAccess, SeqAccess, Visitor};
+use serde::Deserializer;
+use serde_json::Value;
+use std::fmt;
 use std::io::Write;
+use std::marker::PhantomData;
+use std::path::Path;
 use strum_macros::{Display, EnumString, EnumVariantNames};
 
-#[derive(EnumString, EnumVariantNames, Display)]
+const SQLITE_MAGIC: &[u8] = b"SQLite format 3\0";
+const PARQUET_MAGIC: &[u8] = b"PAR1";
+
+#[derive(EnumString, EnumVariantNames, Display, Clone, Copy, PartialEq)]
 #[strum(serialize_all = "kebab_case")]
 pub enum Format {
     Json,
     Csv,
+    Parquet,
+    Sqlite,
 }
 
 impl Format {
-    pub fn parse_file(&self, file: File) -> Box<dyn Iterator<Item = DirectoryStat>> {
-        let reader = io::BufReader::new(file);
-        match self {
+    // The format matching a file's extension, such as `.csv` or `.csv.gz`.
+    pub fn from_extension(path: &Path) -> Option<Format> {
+        let path = match Compression::from_extension(path) {
+            Compression::None => path,
+            _ => Path::new(path.file_stem()?),
+        };
+        path.extension()?.to_str()?.to_lowercase().parse().ok()
+    }
+
+    // Detect the format of a results file. SQLite and Parquet files are recognised by their
+    // magic bytes, whatever they're named, and anything else is JSON or CSV, possibly
+    // compressed. Those are told apart by the extension, or failing that the contents: JSON
+    // records and the JSON header start with `{`, CSV output starts with its metadata comment or
+    // the column names.
+    pub fn detect(path: &Path) -> Option<Format> {
+        // Left to whatever reads the file to report why it can't be opened.
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(_) => return Format::from_extension(path),
+        };
+        let mut file = io::BufReader::new(file);
+        let start = file.fill_buf().ok()?;
+        if start.starts_with(SQLITE_MAGIC) {
+            return Some(Format::Sqlite);
+        }
+        if start.starts_with(PARQUET_MAGIC) {
+            return Some(Format::Parquet);
+        }
+        if let Some(format @ (Format::Json | Format::Csv)) = Format::from_extension(path) {
+            return Some(format);
+        }
+        let mut line = String::new();
+        compression::open_decompressed(path)
+            .ok()?
+            .read_line(&mut line)
+            .ok()?;
+        if line.starts_with('{') {
+            Some(Format::Json)
+        } else if line.starts_with('#') || line.starts_with("total_size,") {
+            Some(Format::Csv)
+        } else {
+            None
+        }
+    }
+
+    pub fn parse_file(&self, path: &Path) -> Result<Stats, ParseError> {
+        self.parse_prefix(path, Path::new(""))
+    }
+
+    // Only the directories at or beneath `prefix`. SQLite databases are queried using their
+    // index, other formats are read in full.
+    pub fn parse_prefix(&self, path: &Path, prefix: &Path) -> Result<Stats, ParseError> {
+        if let Self::Sqlite | Self::Parquet = self {
+            if let Some(metadata) = self.read_metadata(path)? {
+                metadata.check_version(path);
+                metadata.check_partial(path);
+            }
+        }
+        if let Self::Sqlite = self {
+            return sqlite_format::parse_file(path, prefix);
+        }
+        if let Self::Parquet = self {
+            let file = File::open(path)?;
+            return Ok(filter_prefix(parquet_format::parse_file(file)?, prefix));
+        }
+
+        // Only the header is read beforehand. Whether the scan was interrupted is recorded at the
+        // end, which is checked once the records have all been read.
+        if let Some(header) = read_header(path)? {
+            header.check_version(path);
+        }
+        let (reader, trailer) = MetadataObserver::new(compression::open_decompressed(path)?);
+        let reader = io::BufReader::new(reader);
+        let stats: Stats = match self {
+            // One record per line, skipping the metadata header.
             Self::Json => Box::new(
-                serde_json::Deserializer::from_reader(reader)
-                    .into_iter::<DirectoryStat>()
-                    .map(|f| f.unwrap()),
+                reader
+                    .lines()
+                    .enumerate()
+                    .filter(|(_, line)| {
+                        line.as_ref().map_or(true, |line| {
+                            !line.is_empty() && !metadata::is_json_header(line)
+                        })
+                    })
+                    .map(|(index, line)| {
+                        serde_json::from_str::<DirectoryStat>(&line?)
+                            .map_err(|e| json_error(index + 1, e))
+                    }),
             ),
             Self::Csv => Box::new(
-                csv::Reader::from_reader(reader)
+                csv::ReaderBuilder::new()
+                    .comment(Some(b'#'))
+                    .from_reader(reader)
                     .into_deserialize::<DirectoryStat>()
-                    .map(|f| f.unwrap()),
+                    .map(|stat| stat.map_err(ParseError::from)),
             ),
+            Self::Parquet | Self::Sqlite => unreachable!(),
+        };
+        let path = path.to_path_buf();
+        let check_partial = std::iter::from_fn(move || {
+            if let Some(metadata) = trailer.take() {
+                metadata.check_partial(&path);
+            }
+            None
+        });
+        Ok(filter_prefix(Box::new(stats.chain(check_partial)), prefix))
+    }
+
+    // The metadata describing how the file was produced, if it has any. JSON and CSV output
+    // also has a record at the end once the scan finishes, which is used instead. Finding it in
+    // compressed output means decompressing the whole file, so this is only used where the
+    // records themselves aren't read, such as `--metadata`.
+    pub fn read_metadata(&self, path: &Path) -> Result<Option<ScanMetadata>, ParseError> {
+        match self {
+            Self::Json | Self::Csv => {
+                let header = match read_header(path)? {
+                    Some(header) => header,
+                    None => return Ok(None),
+                };
+                let trailer = if compression::is_compressed(path)? {
+                    // The end of a compressed file can only be found by decompressing it all.
+                    let (mut reader, trailer) =
+                        MetadataObserver::new(compression::open_decompressed(path)?);
+                    io::copy(&mut reader, &mut io::sink())?;
+                    trailer.take()
+                } else {
+                    metadata::read_trailer(path)?
+                };
+                Ok(Some(trailer.unwrap_or(header)))
+            }
+            Self::Parquet => parquet_format::read_metadata(File::open(path)?),
+            Self::Sqlite => sqlite_format::read_metadata(path),
         }
     }
 
-    pub fn get_writer(&self, file: Box<dyn io::Write>) -> Box<dyn FormatWriter> {
+    // The metadata written when the scan started, which is all that's needed to know the options
+    // it was run with. Unlike `read_metadata`, this only reads the start of JSON and CSV output.
+    pub fn read_header(&self, path: &Path) -> Result<Option<ScanMetadata>, ParseError> {
         match self {
-            Self::Json => Box::new(JsonWriter::new(file)),
-            Self::Csv => Box::new(CSVWriter::new(file)),
+            Self::Json | Self::Csv => read_header(path),
+            Self::Parquet | Self::Sqlite => self.read_metadata(path),
         }
     }
+
+    pub fn get_writer<R: Record>(&self, file: Encoder) -> io::Result<Box<dyn FormatWriter<R>>> {
+        match self {
+            Self::Json => Ok(Box::new(JsonWriter::new(file))),
+            Self::Csv => Ok(Box::new(CSVWriter::new(file))),
+            Self::Parquet => Ok(Box::new(ParquetWriter::new(Box::new(file), R::COLUMNS))),
+            Self::Sqlite => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "SQLite output must be written to a file",
+            )),
+        }
+    }
+
+    // Can more records be appended to existing output in this format?
+    pub fn is_appendable(&self) -> bool {
+        !matches!(self, Self::Parquet | Self::Sqlite)
+    }
+
+    // Parquet and SQLite files are read in place, so can't be compressed as a whole. Parquet
+    // compresses each column itself.
+    pub fn is_compressible(&self) -> bool {
+        !matches!(self, Self::Parquet | Self::Sqlite)
+    }
+
+    // Get a writer that appends to existing output, e.g when resuming a scan.
+    pub fn get_appending_writer(&self, file: Encoder) -> io::Result<Box<dyn FormatWriter>> {
+        match self {
+            Self::Json => Ok(Box::new(JsonWriter::new(file))),
+            Self::Csv => {
+                let mut writer = CSVWriter::new(file);
+                writer.written_headers = true;
+                Ok(Box::new(writer))
+            }
+            Self::Parquet | Self::Sqlite => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} output can't be appended to", self),
+            )),
+        }
+    }
+}
+
+// The metadata record at the start of JSON or CSV output, which only needs the start of the
+// file to be read. Output always has a header or column names, so an empty file was cut short
+// before anything was written to it.
+fn read_header(path: &Path) -> Result<Option<ScanMetadata>, ParseError> {
+    let mut reader = compression::open_decompressed(path)?;
+    let mut line = vec![];
+    if reader.read_until(b'\n', &mut line)? == 0 {
+        return Err(ParseError::InvalidFile("The file is empty".to_string()));
+    }
+    Ok(metadata::parse_header(
+        String::from_utf8_lossy(&line).trim_end(),
+    ))
+}
+
+// Errors are kept, as it isn't known which directory they were for.
+fn filter_prefix(stats: Stats, prefix: &Path) -> Stats {
+    let prefix = prefix.to_path_buf();
+    Box::new(stats.filter(move |stat| {
+        stat.as_ref()
+            .map_or(true, |stat| stat.path.starts_with(&prefix))
+    }))
 }
 
-pub trait FormatWriter {
-    fn new(_: Box<dyn io::Write>) -> Self
-    where
-        Self: Sized;
-    fn write_stat(&mut self, stat: &DirectoryStat) -> io::Result<()>;
+// Report entries that can't be parsed and carry on, rather than stopping at the first one.
+// Errors reading the file itself are still returned.
+pub fn skip_invalid(stats: Stats, path: &Path) -> Stats {
+    let path = path.to_path_buf();
+    Box::new(stats.filter(move |stat| match stat {
+        Err(error) if error.is_recoverable() => {
+            eprintln!("Skipping invalid entry in {}: {}", path.display(), error);
+            false
+        }
+        _ => true,
+    }))
+}
+
+pub type Stats = Box<dyn Iterator<Item = Result<DirectoryStat, ParseError>>>;
+
+#[derive(Debug)]
+pub enum ParseError {
+    // The file couldn't be opened or read.
+    Io(io::Error),
+    // The file isn't valid in the expected format, so nothing more can be read from it.
+    InvalidFile(String),
+    // A single entry couldn't be parsed. The entries after it may still be readable.
+    InvalidRecord { location: String, message: String },
+}
+
+impl ParseError {
+    pub fn is_recoverable(&self) -> bool {
+        matches!(self, ParseError::InvalidRecord { .. })
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::Io(error) => write!(f, "{}", error),
+            ParseError::InvalidFile(message) => write!(f, "{}", message),
+            ParseError::InvalidRecord { location, message } => {
+                write!(f, "{}: {}", location, message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl From<io::Error> for ParseError {
+    fn from(error: io::Error) -> Self {
+        ParseError::Io(error)
+    }
+}
+
+// Each line is parsed on its own, so the position within the line is all serde_json adds.
+fn json_error(line: usize, error: serde_json::Error) -> ParseError {
+    let message = error.to_string();
+    let message = match message.rsplit_once(" at line ") {
+        Some((message, _)) => message.to_string(),
+        None => message,
+    };
+    ParseError::InvalidRecord {
+        location: format!("line {} column {}", line, error.column()),
+        message,
+    }
+}
+
+impl From<csv::Error> for ParseError {
+    fn from(error: csv::Error) -> Self {
+        let location = match error.position() {
+            Some(position) => format!("record {} (line {})", position.record(), position.line()),
+            None => "unknown record".to_string(),
+        };
+        let message = error.to_string();
+        match error.into_kind() {
+            csv::ErrorKind::Io(error) => ParseError::Io(error),
+            csv::ErrorKind::Deserialize { err, .. } => ParseError::InvalidRecord {
+                location,
+                message: err.to_string(),
+            },
+            csv::ErrorKind::UnequalLengths {
+                expected_len, len, ..
+            } => ParseError::InvalidRecord {
+                location,
+                message: format!("expected {} fields but found {}", expected_len, len),
+            },
+            _ => ParseError::InvalidRecord { location, message },
+        }
+    }
+}
+
+// Writes one kind of record, the directories found by a scan unless otherwise given.
+pub trait FormatWriter<R: Record = DirectoryStat> {
+    // Describe how the results were produced. Called when a scan starts and again when it
+    // finishes or is interrupted, so formats that can't update what they've written append it.
+    fn write_metadata(&mut self, _metadata: &ScanMetadata) -> io::Result<()> {
+        Ok(())
+    }
+    fn write_record(&mut self, record: &R) -> io::Result<()>;
+    fn flush(&mut self) -> io::Result<()>;
+    // Write anything still buffered and complete the output. Nothing is written when a writer
+    // is dropped without being finished, so this is where any error is reported.
+    fn finish(mut self: Box<Self>) -> io::Result<()> {
+        self.flush()
+    }
 }
 
 pub struct JsonWriter {
-    writer: Box<dyn io::Write>,
+    writer: Encoder,
 }
 
-impl FormatWriter for JsonWriter {
-    fn new(writer: Box<dyn io::Write>) -> Self {
+impl JsonWriter {
+    pub fn new(writer: Encoder) -> Self {
         JsonWriter { writer }
     }
+}
 
-    fn write_stat(&mut self, stat: &DirectoryStat) -> io::Result<()> {
-        let res = serde_json::to_vec(stat).expect("Error serializing directory stat");
+impl<R: Record> FormatWriter<R> for JsonWriter {
+    fn write_metadata(&mut self, metadata: &ScanMetadata) -> io::Result<()> {
+        writeln!(self.writer, "{}", metadata::json_header(metadata))
+    }
+
+    fn write_record(&mut self, record: &R) -> io::Result<()> {
+        let res = serde_json::to_vec(record).expect("Error serializing record");
         self.writer.write_all(&res)?;
         writeln!(self.writer)?;
         Ok(())
     }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        self.writer.finish()
+    }
 }
 
 pub struct CSVWriter {
-    csv_writer: csv::Writer<Box<dyn io::Write>>,
+    csv_writer: csv::Writer<Encoder>,
+    written_headers: bool,
 }
 
-impl FormatWriter for CSVWriter {
-    fn new(writer: Box<dyn Write>) -> Self {
-        let csv_writer = csv::WriterBuilder::new()
-            .has_headers(true)
-            .from_writer(writer);
-        CSVWriter { csv_writer }
+impl CSVWriter {
+    pub fn new(writer: Encoder) -> Self {
+        CSVWriter {
+            csv_writer: csv_writer(writer),
+            written_headers: false,
+        }
     }
 
-    fn write_stat(&mut self, stat: &DirectoryStat) -> io::Result<()> {
-        self.csv_writer.serialize(stat)?;
+    // The output beneath the CSV writer, once everything it has buffered has been written.
+    fn take_writer(&mut self) -> io::Result<Encoder> {
+        let sink = csv_writer(Encoder::None(Box::new(io::sink())));
+        std::mem::replace(&mut self.csv_writer, sink)
+            .into_inner()
+            .map_err(|e| io::Error::other(e.to_string()))
+    }
+}
+
+fn csv_writer(writer: Encoder) -> csv::Writer<Encoder> {
+    csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_writer(writer)
+}
+
+impl<R: Record> FormatWriter<R> for CSVWriter {
+    // Written as a comment before the column names and after the last record, which CSV
+    // readers can be told to skip.
+    // The CSV writer can only write records, so the comment is written to the output directly.
+    fn write_metadata(&mut self, metadata: &ScanMetadata) -> io::Result<()> {
+        let mut writer = self.take_writer()?;
+        writeln!(writer, "{}", metadata::csv_header(metadata))?;
+        self.csv_writer = csv_writer(writer);
+        Ok(())
+    }
+
+    // Every record of a kind has the same columns, whichever of its fields are set.
+    fn write_record(&mut self, record: &R) -> io::Result<()> {
+        if !self.written_headers {
+            self.csv_writer
+                .write_record(R::COLUMNS.iter().map(|(name, _)| name))?;
+            self.written_headers = true;
+        }
+        // Nested values are written as JSON, which `deserialize_nested` reads back.
+        let row = columns::to_row(record)?
+            .into_iter()
+            .map(|value| match value {
+                Value::Null => String::new(),
+                Value::String(string) => string,
+                other => other.to_string(),
+            });
+        self.csv_writer.write_record(row)?;
         Ok(())
     }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.csv_writer.flush()
+    }
+
+    fn finish(mut self: Box<Self>) -> io::Result<()> {
+        self.take_writer()?.finish()
+    }
+}
+
+/// Deserialize an optional nested value, which is either stored as-is (JSON) or as a string
+/// containing JSON (CSV).
+pub fn deserialize_nested<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: DeserializeOwned,
+{
+    deserializer.deserialize_any(NestedVisitor(PhantomData))
+}
+
+struct NestedVisitor<T>(PhantomData<T>);
+
+impl<'de, T: DeserializeOwned> Visitor<'de> for NestedVisitor<T> {
+    type Value = Option<T>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a nested value or a string containing JSON")
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<Self::Value, E> {
+        Ok(None)
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<Self::Value, E> {
+        Ok(None)
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Self::Value, E> {
+        if value.is_empty() {
+            return Ok(None);
+        }
+        serde_json::from_str(value).map(Some).map_err(E::custom)
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Self::Value, A::Error> {
+        T::deserialize(MapAccessDeserializer::new(map)).map(Some)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, seq: A) -> Result<Self::Value, A::Error> {
+        T::deserialize(SeqAccessDeserializer::new(seq)).map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::directory_stat::tests::stat;
+    use crate::directory_stat::BreakdownStat;
+    use chrono::Utc;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn csv_records_with_different_fields_can_be_read_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.csv");
+
+        let plain = stat("/a/b", 10, 1);
+        let mut with_extensions = stat("/a", 20, 2);
+        let mut extensions = BTreeMap::new();
+        extensions.insert(
+            "txt".to_string(),
+            BreakdownStat {
+                file_count: 2,
+                total_size: 20,
+            },
+        );
+        with_extensions.extensions = Some(extensions);
+        with_extensions.latest_modified = Some(Utc::now());
+
+        let mut writer: Box<dyn FormatWriter> = Format::Csv
+            .get_writer(Compression::None.wrap_writer(File::create(&path).unwrap()))
+            .unwrap();
+        writer.write_record(&plain).unwrap();
+        writer.write_record(&with_extensions).unwrap();
+        writer.finish().unwrap();
+
+        let stats: Vec<_> = Format::Csv
+            .parse_file(&path)
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(stats.len(), 2);
+        assert_eq!(stats[0].path, Path::new("/a/b"));
+        assert!(stats[0].extensions.is_none());
+        assert!(stats[0].latest_modified.is_none());
+        assert_eq!(stats[1].path, Path::new("/a"));
+        assert_eq!(stats[1].total_size, 20);
+        assert_eq!(stats[1].extensions.as_ref().unwrap()["txt"].file_count, 2);
+        assert_eq!(stats[1].latest_modified, with_extensions.latest_modified);
+    }
+
+    #[test]
+    fn binary_formats_are_detected_before_the_extension() {
+        let dir = tempfile::tempdir().unwrap();
+        let write = |name: &str, compression: Compression, contents: &[u8]| {
+            let path = dir.path().join(name);
+            let mut writer = compression.wrap_writer(File::create(&path).unwrap());
+            writer.write_all(contents).unwrap();
+            writer.finish().unwrap();
+            path
+        };
+        let detected = |path: &Path| Format::detect(path).map(|format| format.to_string());
+
+        let sqlite = write("misnamed.json", Compression::None, SQLITE_MAGIC);
+        assert_eq!(detected(&sqlite).as_deref(), Some("sqlite"));
+        let parquet = write("misnamed.csv", Compression::None, PARQUET_MAGIC);
+        assert_eq!(detected(&parquet).as_deref(), Some("parquet"));
+        // Compressed output can only be JSON or CSV.
+        let json = write("misnamed.parquet.gz", Compression::Gzip, b"{}\n");
+        assert_eq!(detected(&json).as_deref(), Some("json"));
+        let csv = write("scan.zst", Compression::Zstd, b"total_size,file_count\n");
+        assert_eq!(detected(&csv).as_deref(), Some("csv"));
+        let csv = write("scan.csv", Compression::None, b"{}\n");
+        assert_eq!(detected(&csv).as_deref(), Some("csv"));
+
+        let empty = write("empty.csv.gz", Compression::Gzip, b"");
+        assert_eq!(detected(&empty).as_deref(), Some("csv"));
+        let error = Format::Csv.parse_file(&empty).err().unwrap();
+        assert_eq!(error.to_string(), "The file is empty");
+    }
 }
@@ -1,29 +1,58 @@
-use crate::args::{Args, Command, SortType};
-use crate::formats::Format;
+use crate::args::{Args, Breakdown, Command, DiffSortType, SortType};
+use crate::baseline::Baseline;
+use crate::checkpoint::{Checkpoint, Checkpointer, CHECKPOINT_INTERVAL};
+use crate::columns::Record;
+use crate::compression::{Compression, Encoder};
+use crate::dupes::{DuplicateGroup, Duplicates};
+use crate::error_log::{ErrorLog, Operation};
+use crate::formats::{Format, FormatWriter, ParseError, Stats};
+use crate::merge::MergedStats;
+use crate::metadata::ScanMetadata;
+use crate::options::ScanOptions;
+use crate::owners::NameCache;
 use crate::progress::WalkProgress;
+use crate::sqlite_format::SqliteWriter;
 use crate::state::WalkState;
-use crate::walker::Walker;
+use crate::walker::{WalkDirEntry, Walker};
 
 use std::fs::File;
 use std::io;
 
-use crate::directory_stat::DirectoryStat;
+use crate::directory_stat::{add_largest_file, AgeStat, BreakdownStat, DirectoryStat, FileStat};
 use chrono_humanize::Humanize;
 use indicatif::HumanBytes;
-use prettytable::{cell, row, Table};
-use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::io::{BufWriter, ErrorKind, Write};
-use std::path::PathBuf;
+use prettytable::{row, Cell, Table};
+use rayon::ThreadPool;
+use std::collections::{BTreeSet, HashMap};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use structopt::StructOpt;
 
 mod args;
+mod baseline;
+mod checkpoint;
+mod columns;
+mod compression;
 mod directory_stat;
+mod dupes;
+mod error_log;
+mod filter;
 mod formats;
+mod merge;
+mod metadata;
+mod options;
+mod owners;
+mod parquet_format;
 mod progress;
+mod sqlite_format;
 mod state;
 mod walker;
 
+// The conventional exit code for a process stopped by SIGINT.
+const INTERRUPTED_EXIT_CODE: i32 = 130;
+
 fn main() {
     reset_signal_pipe_handler().expect("Error resetting signal pipe handler");
     let args: Args = Args::from_args();
@@ -31,33 +60,188 @@ fn main() {
         Command::Scan {
             threads,
             ignore_hidden,
+            one_file_system,
             actual_size,
             output,
-            path,
+            paths,
+            roots_from,
             format,
+            compress,
             depth,
-        } => walk(
-            path,
-            ignore_hidden,
-            threads.unwrap_or(num_cpus::get() * 2),
-            actual_size,
-            format,
-            output,
-            depth,
-        ),
+            filter,
+            extensions,
+            owners,
+            histogram,
+            age_buckets,
+            largest_files,
+            checkpoint,
+            resume,
+            baseline,
+            errors,
+        } => {
+            // Set when the scan is asked to stop with Ctrl-C or SIGTERM, so that it can write out
+            // what it has seen so far.
+            let interrupted = Arc::new(AtomicBool::new(false));
+            handle_interrupts(interrupted.clone());
+            let roots = get_roots(paths, roots_from);
+            if checkpoint.is_some() && !format.is_appendable() {
+                eprintln!(
+                    "Scans in {} format can't be resumed from a checkpoint",
+                    format
+                );
+                std::process::exit(1);
+            }
+            if checkpoint.is_some()
+                && Compression::for_output(compress, &output) != Compression::None
+            {
+                eprintln!("Compressed scans can't be resumed from a checkpoint");
+                std::process::exit(1);
+            }
+            let options = ScanOptions {
+                actual_size,
+                ignore_hidden,
+                one_file_system,
+                depth,
+                filters: filter.to_args(),
+                extensions,
+                owners,
+                histogram,
+                age_buckets: age_buckets.clone(),
+                largest_files,
+            };
+            // The baseline may have been written in a different format to this scan.
+            let baseline = baseline.map(|baseline| {
+                let baseline_format = Format::detect(&baseline).unwrap_or(format);
+                Arc::new(exit_on_error(
+                    &baseline,
+                    Baseline::load(baseline.clone(), &baseline_format, &options),
+                ))
+            });
+            let resumed = match (&checkpoint, resume) {
+                (Some(checkpoint), true) => load_checkpoint(checkpoint, &roots, &format, &options),
+                _ => None,
+            };
+            let (writer, metadata) = match &resumed {
+                None => {
+                    let mut writer = get_writer(&format, output.clone(), compress);
+                    let metadata = ScanMetadata {
+                        options: options.clone(),
+                        ..ScanMetadata::new(roots.clone())
+                    };
+                    writer
+                        .write_metadata(&metadata)
+                        .expect("Error writing metadata");
+                    (writer, Some(metadata))
+                }
+                Some(resumed) => {
+                    let output = output.clone().unwrap();
+                    let writer = exit_on_write_error(
+                        resumed
+                            .open_output(&output)
+                            .and_then(|file| format.get_appending_writer(file)),
+                    );
+                    // Keep the header written when the scan started, as the resumed scan
+                    // continues it.
+                    let metadata = exit_on_error(&output, format.read_header(&output));
+                    (writer, metadata)
+                }
+            };
+            let checkpointer = checkpoint.map(|checkpoint| {
+                Checkpointer::new(
+                    checkpoint,
+                    output.unwrap(),
+                    roots.clone(),
+                    format.to_string(),
+                    options,
+                    CHECKPOINT_INTERVAL,
+                )
+            });
+            let threads = threads.unwrap_or(num_cpus::get() * 2);
+            let thread_pool = Arc::new(
+                rayon::ThreadPoolBuilder::new()
+                    .num_threads(threads)
+                    .build()
+                    .expect("Error creating thread pool"),
+            );
+            let error_log = Arc::new(ErrorLog::new(errors).expect("Error creating error log"));
+            let walker = Walker::new(threads, actual_size, ignore_hidden, true, true)
+                .filter(filter.into())
+                .thread_pool(thread_pool)
+                .one_file_system(one_file_system)
+                .resume_from(resumed.as_ref().map(|r| r.resume_from.clone()))
+                .baseline(baseline.clone())
+                .error_log(Some(error_log.clone()))
+                .interrupted(Some(interrupted.clone()));
+            let mut walk_state = WalkState::new(writer, depth)
+                .extensions(extensions)
+                .owners(owners)
+                .histogram(histogram)
+                .age_buckets(age_buckets)
+                .largest_files(largest_files)
+                .checkpoint(checkpointer)
+                .baseline(baseline)
+                .error_log(Some(error_log.clone()))
+                .metadata(metadata);
+            if let Some(resumed) = resumed {
+                walk_state = walk_state.resume(resumed);
+            }
+            let status = walk(roots, walker, walk_state, &error_log, &interrupted);
+            if status != 0 {
+                std::process::exit(status);
+            }
+        }
         Command::Stream {
             threads,
             ignore_hidden,
+            one_file_system,
             path,
             no_size,
             only_paths,
+            filter,
         } => stream(
             path,
-            ignore_hidden,
-            threads.unwrap_or(num_cpus::get() * 2),
-            no_size,
+            Walker::new(
+                threads.unwrap_or(num_cpus::get() * 2),
+                false,
+                ignore_hidden,
+                !(no_size || only_paths),
+                false,
+            )
+            .filter(filter.into())
+            .one_file_system(one_file_system),
             only_paths,
         ),
+        Command::Dupes {
+            threads,
+            ignore_hidden,
+            one_file_system,
+            output,
+            path,
+            format,
+            compress,
+            filter,
+            errors,
+        } => {
+            let threads = threads.unwrap_or(num_cpus::get() * 2);
+            let thread_pool = Arc::new(
+                rayon::ThreadPoolBuilder::new()
+                    .num_threads(threads)
+                    .build()
+                    .expect("Error creating thread pool"),
+            );
+            let error_log = Arc::new(ErrorLog::new(errors).expect("Error creating error log"));
+            dupes(
+                path,
+                Walker::new(threads, false, ignore_hidden, true, false)
+                    .filter(filter.into())
+                    .one_file_system(one_file_system)
+                    .thread_pool(thread_pool.clone())
+                    .error_log(Some(error_log.clone())),
+                &thread_pool,
+                &error_log,
+                get_writer(&format, output, compress),
+            )
+        }
         Command::Parse {
             depth,
             prefix,
@@ -65,66 +249,260 @@ fn main() {
             input,
             format,
             sort,
-        } => read(depth, prefix, input, format, sort, limit),
+            by,
+            histogram,
+            cold,
+            earliest,
+            largest_files,
+            metadata,
+            skip_invalid,
+        } => {
+            let prefix = PathBuf::from(prefix);
+            let format = input_format(format, &input);
+            if metadata {
+                exit_on_error(&input, print_metadata(&input, &format));
+            } else if histogram {
+                let stats = parse_input(&input, &prefix, &format, skip_invalid);
+                exit_on_error(&input, stats.and_then(print_histogram));
+            } else if largest_files {
+                let stats = parse_input(&input, &prefix, &format, skip_invalid);
+                let limit = match limit {
+                    Some(limit) => Some(limit),
+                    None => exit_on_error(&input, recorded_largest_files(&input, &format)),
+                };
+                exit_on_error(
+                    &input,
+                    stats.and_then(|stats| print_largest_files(stats, limit)),
+                );
+            } else {
+                let stats =
+                    exit_on_error(&input, read(depth, &prefix, &input, &format, skip_invalid));
+                match (by, cold) {
+                    (Some(by), _) => print_breakdown(&prefix, stats, by, sort, limit),
+                    (None, Some(days)) => print_cold(&prefix, stats, days, limit),
+                    (None, None) => print_directories(&prefix, stats, sort, limit, earliest),
+                }
+            }
+        }
+        Command::Merge {
+            output,
+            format,
+            compress,
+            input_format,
+            inputs,
+            skip_invalid,
+        } => {
+            // Directories in several inputs keep as many largest files as the most any input
+            // recorded, or all of them if an input is from a scan without a metadata header.
+            let mut largest_files = Some(0);
+            let inputs = inputs
+                .into_iter()
+                .map(|input| {
+                    let format = Format::detect(&input).unwrap_or(input_format);
+                    match exit_on_error(&input, format.read_header(&input)) {
+                        Some(metadata) => {
+                            let recorded = metadata.options.largest_files.unwrap_or(0);
+                            largest_files = largest_files.map(|limit| limit.max(recorded));
+                        }
+                        None => largest_files = None,
+                    }
+                    let stats = parse_input(&input, Path::new(""), &format, skip_invalid);
+                    let stats = exit_on_error(&input, stats);
+                    (input, stats)
+                })
+                .collect();
+            merge(
+                MergedStats::new(inputs, largest_files),
+                get_writer(&format, output, compress),
+            );
+        }
+        Command::Diff {
+            depth,
+            prefix,
+            limit,
+            old,
+            new,
+            format,
+            sort,
+            skip_invalid,
+        } => {
+            let prefix = PathBuf::from(prefix);
+            let old_format = input_format(format, &old);
+            let new_format = input_format(format, &new);
+            let old_stats =
+                exit_on_error(&old, read(depth, &prefix, &old, &old_format, skip_invalid));
+            let new_stats =
+                exit_on_error(&new, read(depth, &prefix, &new, &new_format, skip_invalid));
+            print_diff(&prefix, old_stats, new_stats, sort, limit);
+        }
     }
 }
 
+// Walk each root in turn on the shared thread pool, writing them all to the same output. A root
+// is only walked once the one before it has been written, so just one walk is held in memory.
+// Returns the status to exit with, which is INTERRUPTED_EXIT_CODE if the walk was interrupted
+// and the output only covers what had been walked so far.
 pub fn walk(
-    root: PathBuf,
-    ignore_hidden: bool,
-    threads: usize,
-    actual_size: bool,
-    format: Format,
-    output: Option<PathBuf>,
-    depth: Option<usize>,
-) {
-    let writer = format.get_writer(get_output_file(output));
-
-    let walker = Walker::new(threads, actual_size, ignore_hidden, true, true);
+    roots: Vec<PathBuf>,
+    walker: Walker,
+    walk_state: WalkState,
+    error_log: &ErrorLog,
+    interrupted: &AtomicBool,
+) -> i32 {
+    let walk_root = |root: &PathBuf| {
+        if walker.is_finished(root) {
+            None
+        } else {
+            Some(walker.walk_dir(root))
+        }
+    };
+    write_walks(roots, walk_root, walk_state, error_log, interrupted)
+}
 
-    let mut walk_state = WalkState::new(writer, depth);
-    let mut walk_progress = WalkProgress::new(root.clone());
+// Write the entries of each root's walk, or skip the root if there's no walk for it. Separate
+// from `walk` so that tests can interrupt a walk at a known entry.
+fn write_walks<I>(
+    roots: Vec<PathBuf>,
+    walk_root: impl Fn(&PathBuf) -> Option<I>,
+    mut walk_state: WalkState,
+    error_log: &ErrorLog,
+    interrupted: &AtomicBool,
+) -> i32
+where
+    I: Iterator<Item = jwalk::Result<WalkDirEntry>>,
+{
+    let mut walk_progress = WalkProgress::new(roots.clone());
     let progress_bar = walk_progress.create_progress_bar();
 
-    for dir in &mut walker.walk_dir(&root) {
-        walk_progress.record_progress(&dir);
-        if walk_progress.should_update() {
-            walk_progress.update(&progress_bar);
-        }
+    'roots: for (index, root) in roots.iter().enumerate() {
+        let walk = match walk_root(root) {
+            Some(walk) => walk,
+            None => continue,
+        };
+        walk_progress.start_root(index);
 
-        let dir_entry = dir.unwrap();
+        for dir in walk {
+            if interrupted.load(Ordering::SeqCst) {
+                break 'roots;
+            }
+            walk_progress.record_progress(&dir);
+            if walk_progress.should_update() {
+                walk_progress.update(&progress_bar);
+            }
 
-        if let Some(metadata) = &dir_entry.client_state {
-            if dir_entry.file_type.is_dir() {
-                walk_state.add_path(dir_entry.path(), metadata);
-            } else {
-                walk_state.add_path(dir_entry.parent_path.to_path_buf(), metadata);
+            let dir_entry = match dir {
+                Ok(dir_entry) => dir_entry,
+                Err(error) => {
+                    let directory = error.path().unwrap_or(root).to_path_buf();
+                    error_log.record_walk_error(&directory, Operation::ReadDir, &error);
+                    continue;
+                }
             };
+            if let Some(error) = &dir_entry.read_children_error {
+                error_log.record_walk_error(&dir_entry.path(), Operation::ReadDir, error);
+            }
+
+            exit_on_write_error(walk_state.add_entry(&dir_entry));
         }
     }
 
+    let interrupted = interrupted.load(Ordering::SeqCst);
+    let largest_files = exit_on_write_error(walk_state.finish(interrupted));
+    error_log.flush().expect("Error writing error log");
+
     progress_bar.finish_and_clear();
+    walk_progress.set_error_kinds(error_log.counts());
     eprintln!("{}", walk_progress);
+    if let Some(largest_files) = largest_files {
+        eprintln!("{}", largest_files_table(&largest_files));
+    }
+    if interrupted {
+        eprintln!("Scan interrupted, the results written so far are partial");
+        INTERRUPTED_EXIT_CODE
+    } else {
+        0
+    }
 }
 
-// Temporary hack
-#[derive(Serialize, Deserialize, Clone)]
-pub struct FileStat {
-    pub path: PathBuf,
-    pub size: u64,
+// The roots given on the command line and in the --roots-from file, sorted so that they are
+// written in the same order as a single scan would. Roots inside another root are skipped, as
+// they would otherwise be counted twice.
+fn get_roots(mut paths: Vec<PathBuf>, roots_from: Option<PathBuf>) -> Vec<PathBuf> {
+    if let Some(roots_from) = roots_from {
+        let contents = std::fs::read_to_string(roots_from).expect("Error reading roots file");
+        paths.extend(
+            contents
+                .lines()
+                .map(|line| line.trim())
+                .filter(|line| !line.is_empty())
+                .map(PathBuf::from),
+        );
+    }
+    paths.sort();
+    paths.dedup();
+
+    let mut roots: Vec<PathBuf> = vec![];
+    for path in paths {
+        match roots.last() {
+            Some(root) if path.starts_with(root) => {
+                eprintln!(
+                    "Skipping {}, as it is inside {}",
+                    path.display(),
+                    root.display()
+                );
+            }
+            _ => roots.push(path),
+        }
+    }
+    roots
 }
 
-pub fn stream(
-    root: PathBuf,
-    ignore_hidden: bool,
-    threads: usize,
-    mut no_size: bool,
-    only_paths: bool,
-) {
-    if only_paths {
-        no_size = true
+// The checkpoint to resume from, or None to start a new scan if the previous scan completed.
+fn load_checkpoint(
+    path: &PathBuf,
+    roots: &[PathBuf],
+    format: &Format,
+    options: &ScanOptions,
+) -> Option<Checkpoint> {
+    let checkpoint = match Checkpoint::load(path) {
+        Ok(Some(checkpoint)) => checkpoint,
+        Err(e) => {
+            eprintln!("Error loading checkpoint {}: {}", path.display(), e);
+            std::process::exit(1);
+        }
+        Ok(None) => {
+            eprintln!(
+                "There is no checkpoint at {}, so starting a new scan",
+                path.display()
+            );
+            return None;
+        }
+    };
+    if checkpoint.roots != roots || checkpoint.format != format.to_string() {
+        let checkpoint_roots: Vec<_> = checkpoint
+            .roots
+            .iter()
+            .map(|root| root.display().to_string())
+            .collect();
+        eprintln!(
+            "The checkpoint is for a scan of {} in {} format",
+            checkpoint_roots.join(", "),
+            checkpoint.format
+        );
+        std::process::exit(1);
+    }
+    let differences = checkpoint.options.differences(options);
+    if !differences.is_empty() {
+        eprintln!(
+            "The checkpoint is for a scan with different {}",
+            differences.join(", ")
+        );
+        std::process::exit(1);
     }
-    let walker = Walker::new(threads, false, ignore_hidden, !no_size, false);
+    Some(checkpoint)
+}
+
+pub fn stream(root: PathBuf, walker: Walker, only_paths: bool) {
     let stdout = io::stdout();
     let mut output_lock = stdout.lock();
     for dir in &mut walker.walk_dir(&root) {
@@ -162,25 +540,90 @@ pub fn stream(
     }
 }
 
-fn read(
-    depth: usize,
-    prefix: String,
-    input: PathBuf,
-    format: Format,
-    sort_type: SortType,
-    limit: Option<usize>,
+pub fn dupes(
+    root: PathBuf,
+    walker: Walker,
+    thread_pool: &ThreadPool,
+    error_log: &ErrorLog,
+    mut writer: Box<dyn FormatWriter<DuplicateGroup>>,
 ) {
-    let file = File::open(input).expect("Error opening input file");
-    let prefix = PathBuf::from(prefix);
+    let duplicates = Duplicates::find(&root, walker, thread_pool, error_log);
+    for group in &duplicates.groups {
+        writer
+            .write_record(group)
+            .expect("Error writing duplicate group");
+    }
+    exit_on_write_error(writer.finish());
+    eprintln!("{}", duplicates);
+}
+
+pub fn merge(stats: MergedStats, mut writer: Box<dyn FormatWriter>) {
+    let mut count = 0;
+    for stat in stats {
+        let stat = stat.unwrap_or_else(|(input, error)| exit_on_error(&input, Err(error)));
+        writer
+            .write_record(&stat)
+            .expect("Error writing directory statistic");
+        count += 1;
+    }
+    exit_on_write_error(writer.finish());
+    eprintln!("Merged {} directories", count);
+}
+
+// Parse an input file, reporting and skipping any invalid entries if `skip_invalid` is set.
+fn parse_input(
+    input: &Path,
+    prefix: &Path,
+    format: &Format,
+    skip_invalid: bool,
+) -> Result<Stats, ParseError> {
+    let stats = format.parse_prefix(input, prefix)?;
+    if skip_invalid {
+        Ok(formats::skip_invalid(stats, input))
+    } else {
+        Ok(stats)
+    }
+}
+
+// Stop with an error message when the output can't be written.
+fn exit_on_write_error<T>(result: io::Result<T>) -> T {
+    result.unwrap_or_else(|error| {
+        eprintln!("Error writing output: {}", error);
+        std::process::exit(1);
+    })
+}
+
+// Stop with an error message when an input can't be read.
+fn exit_on_error<T>(input: &Path, result: Result<T, ParseError>) -> T {
+    result.unwrap_or_else(|error| {
+        eprintln!("Error reading {}: {}", input.display(), error);
+        std::process::exit(1);
+    })
+}
+
+// The number of largest files the scan recorded in each directory, if it's known.
+fn recorded_largest_files(input: &Path, format: &Format) -> Result<Option<usize>, ParseError> {
+    Ok(format
+        .read_header(input)?
+        .and_then(|metadata| metadata.options.largest_files))
+}
 
-    let items = format.parse_file(file);
-    let filtered_items = items.filter(|p| p.path.starts_with(&prefix));
+fn read(
+    depth: usize,
+    prefix: &Path,
+    input: &Path,
+    format: &Format,
+    skip_invalid: bool,
+) -> Result<HashMap<PathBuf, DirectoryStat>, ParseError> {
+    let largest_files = recorded_largest_files(input, format)?;
+    let filtered_items = parse_input(input, prefix, format, skip_invalid)?;
     let mut stats: HashMap<PathBuf, DirectoryStat> = HashMap::new();
 
     for stat in filtered_items {
+        let stat = stat?;
         let unwrapped_path = &stat.path;
         // Only take the 'depth' number of components, thus truncating the path to a the depth
-        let relative_path = unwrapped_path.strip_prefix(&prefix).unwrap();
+        let relative_path = unwrapped_path.strip_prefix(prefix).unwrap();
         let base_path = PathBuf::new();
         let relative_paths_with_depth =
             relative_path
@@ -193,14 +636,24 @@ fn read(
         for path in relative_paths_with_depth {
             stats
                 .entry(path)
-                .and_modify(|p| p.merge(&stat))
+                .and_modify(|p| p.merge(&stat, largest_files))
                 .or_insert_with(|| stat.clone());
         }
     }
 
+    Ok(stats)
+}
+
+fn print_directories(
+    prefix: &Path,
+    stats: HashMap<PathBuf, DirectoryStat>,
+    sort_type: SortType,
+    limit: Option<usize>,
+    earliest: bool,
+) {
     let mut table = Table::new();
     table.set_format(*prettytable::format::consts::FORMAT_CLEAN);
-    table.set_titles(row![
+    let mut titles = row![
         "Prefix",
         "Files",
         "Size",
@@ -208,9 +661,18 @@ fn read(
         "Created",
         "Accessed",
         "Modified"
-    ]);
+    ];
+    if earliest {
+        titles.add_cell(Cell::new("Earliest Created"));
+        titles.add_cell(Cell::new("Earliest Accessed"));
+        titles.add_cell(Cell::new("Earliest Modified"));
+    }
+    table.set_titles(titles);
 
     let now = chrono::Utc::now();
+    let humanize = |time: Option<chrono::DateTime<chrono::Utc>>| {
+        time.map_or_else(|| "Unknown".to_string(), |c| (c - now).humanize())
+    };
     let mut stats_vec: Vec<_> = stats.into_iter().collect();
 
     match sort_type {
@@ -223,40 +685,487 @@ fn read(
         stats_vec.truncate(limit)
     }
 
+    let incomplete = stats_vec.iter().any(|(_key, stat)| stat.incomplete);
     for (key, value) in stats_vec {
-        let latest_created = value
-            .latest_created
-            .map_or_else(|| "Unknown".to_string(), |c| (c - now).humanize());
-        let latest_accessed = value
-            .latest_accessed
-            .map_or_else(|| "Unknown".to_string(), |c| (c - now).humanize());
-        let latest_modified = value
-            .latest_modified
-            .map_or_else(|| "Unknown".to_string(), |c| (c - now).humanize());
-        table.add_row(row![
-            format!("{}", prefix.as_path().join(key.as_path()).display()),
+        let mut row = row![
+            prefix_cell(prefix, &key, &value),
             value.file_count,
             HumanBytes(value.total_size),
             HumanBytes(value.largest_file_size),
-            latest_created,
-            latest_accessed,
-            latest_modified,
+            humanize(value.latest_created),
+            humanize(value.latest_accessed),
+            humanize(value.latest_modified),
+        ];
+        if earliest {
+            row.add_cell(Cell::new(&humanize(value.earliest_created)));
+            row.add_cell(Cell::new(&humanize(value.earliest_accessed)));
+            row.add_cell(Cell::new(&humanize(value.earliest_modified)));
+        }
+        table.add_row(row);
+    }
+
+    table.printstd();
+    print_incomplete_note(incomplete);
+}
+
+// Directories containing something that couldn't be read are marked, as their totals are only
+// a lower bound.
+fn prefix_cell(prefix: &Path, key: &Path, stat: &DirectoryStat) -> String {
+    let path = prefix.join(key).display().to_string();
+    if stat.incomplete {
+        format!("{} *", path)
+    } else {
+        path
+    }
+}
+
+fn print_incomplete_note(incomplete: bool) {
+    if incomplete {
+        println!("\n* Some files or directories beneath couldn't be read, so the totals are a lower bound.");
+    }
+}
+
+fn print_breakdown(
+    prefix: &Path,
+    stats: HashMap<PathBuf, DirectoryStat>,
+    by: Breakdown,
+    sort_type: SortType,
+    limit: Option<usize>,
+) {
+    let (title, scan_flag) = match by {
+        Breakdown::Extension => ("Extension", "--extensions"),
+        Breakdown::Owner => ("Owner", "--owners"),
+        Breakdown::Group => ("Group", "--owners"),
+    };
+    let mut names = NameCache::default();
+    let mut rows: Vec<(PathBuf, String, u64, u64)> = vec![];
+    let mut found_breakdown = false;
+
+    for (key, value) in stats {
+        let breakdown: Option<Vec<(String, BreakdownStat)>> = match by {
+            Breakdown::Extension => value.extensions.map(|e| e.into_iter().collect()),
+            Breakdown::Owner => value.owners.map(|owners| {
+                owners
+                    .into_iter()
+                    .map(|(uid, stat)| (names.user(uid), stat))
+                    .collect()
+            }),
+            Breakdown::Group => value.groups.map(|groups| {
+                groups
+                    .into_iter()
+                    .map(|(gid, stat)| (names.group(gid), stat))
+                    .collect()
+            }),
+        };
+        if let Some(breakdown) = breakdown {
+            found_breakdown = true;
+            for (name, stat) in breakdown {
+                rows.push((key.clone(), name, stat.file_count, stat.total_size));
+            }
+        }
+    }
+
+    if !found_breakdown {
+        eprintln!(
+            "The input file doesn't contain any {} information. Scan with {} to include it.",
+            by, scan_flag
+        );
+        std::process::exit(1);
+    }
+
+    match sort_type {
+        SortType::Name => rows.sort_by(|a, b| (&a.0, &a.1).cmp(&(&b.0, &b.1))),
+        SortType::Size => rows.sort_by_key(|row| std::cmp::Reverse(row.3)),
+        SortType::Files => rows.sort_by_key(|row| std::cmp::Reverse(row.2)),
+    };
+
+    if let Some(limit) = limit {
+        rows.truncate(limit)
+    }
+
+    let mut table = Table::new();
+    table.set_format(*prettytable::format::consts::FORMAT_CLEAN);
+    table.set_titles(row!["Prefix", title, "Files", "Size"]);
+    for (key, name, file_count, total_size) in rows {
+        table.add_row(row![
+            format!("{}", prefix.join(key.as_path()).display()),
+            name,
+            file_count,
+            HumanBytes(total_size),
+        ]);
+    }
+
+    table.printstd();
+}
+
+fn print_cold(
+    prefix: &Path,
+    stats: HashMap<PathBuf, DirectoryStat>,
+    days: u32,
+    limit: Option<usize>,
+) {
+    let mut found_buckets = BTreeSet::new();
+    let mut rows: Vec<(PathBuf, DirectoryStat, AgeStat)> = vec![];
+    for (key, mut value) in stats {
+        if let Some(mut buckets) = value.age_buckets.take() {
+            found_buckets.extend(buckets.keys().copied());
+            if let Some(age_stat) = buckets.remove(&days) {
+                rows.push((key, value, age_stat));
+            }
+        }
+    }
+
+    if rows.is_empty() {
+        if found_buckets.is_empty() {
+            eprintln!("The input file doesn't contain any age buckets. Scan with --age-buckets to include them.");
+        } else {
+            let available: Vec<_> = found_buckets.iter().map(|d| d.to_string()).collect();
+            eprintln!(
+                "The input file doesn't contain a {} day age bucket. Available buckets: {}",
+                days,
+                available.join(", ")
+            );
+        }
+        std::process::exit(1);
+    }
+
+    rows.sort_by_key(|(_key, _value, age_stat)| std::cmp::Reverse(age_stat.modified.total_size));
+
+    if let Some(limit) = limit {
+        rows.truncate(limit)
+    }
+
+    let mut table = Table::new();
+    table.set_format(*prettytable::format::consts::FORMAT_CLEAN);
+    table.set_titles(row![
+        "Prefix",
+        "Size",
+        format!("Not modified in {} days", days),
+        "Files",
+        format!("Not accessed in {} days", days),
+        "Files",
+    ]);
+    let incomplete = rows.iter().any(|(_key, value, _age_stat)| value.incomplete);
+    for (key, value, age_stat) in rows {
+        let percent = |size: u64| size as f64 / value.total_size.max(1) as f64 * 100.0;
+        table.add_row(row![
+            prefix_cell(prefix, &key, &value),
+            HumanBytes(value.total_size),
+            format!(
+                "{} ({:.1}%)",
+                HumanBytes(age_stat.modified.total_size),
+                percent(age_stat.modified.total_size)
+            ),
+            age_stat.modified.file_count,
+            format!(
+                "{} ({:.1}%)",
+                HumanBytes(age_stat.accessed.total_size),
+                percent(age_stat.accessed.total_size)
+            ),
+            age_stat.accessed.file_count,
         ]);
     }
 
     table.printstd();
+    print_incomplete_note(incomplete);
 }
 
-fn get_output_file(path: Option<PathBuf>) -> Box<dyn io::Write> {
+fn print_diff(
+    prefix: &Path,
+    old_stats: HashMap<PathBuf, DirectoryStat>,
+    mut new_stats: HashMap<PathBuf, DirectoryStat>,
+    sort_type: DiffSortType,
+    limit: Option<usize>,
+) {
+    // The file count and total size of each path in the old and new results.
+    type Totals = Option<(u64, u64)>;
+    let totals = |stat: DirectoryStat| (stat.file_count, stat.total_size);
+    let mut rows: Vec<(PathBuf, Totals, Totals)> = vec![];
+    for (key, old_stat) in old_stats {
+        let new_stat = new_stats.remove(&key).map(totals);
+        rows.push((key, Some(totals(old_stat)), new_stat));
+    }
+    rows.extend(
+        new_stats
+            .into_iter()
+            .map(|(key, new_stat)| (key, None, Some(totals(new_stat)))),
+    );
+    rows.retain(|(_key, old, new)| old != new);
+
+    if rows.is_empty() {
+        eprintln!("No directories have changed");
+        return;
+    }
+
+    let current = |old: &Totals, new: &Totals| new.or(*old).unwrap();
+    let growth =
+        |old: &Totals, new: &Totals| new.map_or(0, |n| n.1) as i64 - old.map_or(0, |o| o.1) as i64;
+    let relative_growth = |old: &Totals, new: &Totals| {
+        let old_size = old.map_or(0, |o| o.1);
+        let growth = growth(old, new);
+        if old_size == 0 {
+            if growth > 0 {
+                f64::INFINITY
+            } else {
+                0.0
+            }
+        } else {
+            growth as f64 / old_size as f64
+        }
+    };
+
+    match sort_type {
+        DiffSortType::Scan(SortType::Name) => rows.sort_by(|a, b| a.0.cmp(&b.0)),
+        DiffSortType::Scan(SortType::Files) => {
+            rows.sort_by_key(|(_key, old, new)| std::cmp::Reverse(current(old, new).0))
+        }
+        DiffSortType::Scan(SortType::Size) => {
+            rows.sort_by_key(|(_key, old, new)| std::cmp::Reverse(current(old, new).1))
+        }
+        DiffSortType::Growth => {
+            rows.sort_by_key(|(_key, old, new)| std::cmp::Reverse(growth(old, new)))
+        }
+        DiffSortType::RelativeGrowth => {
+            rows.sort_by(|a, b| relative_growth(&b.1, &b.2).total_cmp(&relative_growth(&a.1, &a.2)))
+        }
+    };
+
+    if let Some(limit) = limit {
+        rows.truncate(limit)
+    }
+
+    let signed = |delta: i64, display: &dyn Fn(u64) -> String| {
+        let sign = if delta < 0 { "-" } else { "+" };
+        format!("{}{}", sign, display(delta.unsigned_abs()))
+    };
+
+    let mut table = Table::new();
+    table.set_format(*prettytable::format::consts::FORMAT_CLEAN);
+    table.set_titles(row![
+        "Prefix",
+        "Change",
+        "Files",
+        "Files +/-",
+        "Size",
+        "Size +/-",
+        "Growth"
+    ]);
+    for (key, old, new) in rows {
+        let change = match (old, new) {
+            (None, _) => "Added",
+            (_, None) => "Removed",
+            _ => "Changed",
+        };
+        let (file_count, total_size) = new.unwrap_or((0, 0));
+        let file_growth = file_count as i64 - old.map_or(0, |o| o.0) as i64;
+        let relative_growth = relative_growth(&old, &new);
+        table.add_row(row![
+            format!("{}", prefix.join(key.as_path()).display()),
+            change,
+            file_count,
+            signed(file_growth, &|count| count.to_string()),
+            HumanBytes(total_size),
+            signed(growth(&old, &new), &|size| HumanBytes(size).to_string()),
+            if relative_growth.is_infinite() {
+                "new".to_string()
+            } else {
+                format!("{:+.1}%", relative_growth * 100.0)
+            },
+        ]);
+    }
+
+    table.printstd();
+}
+
+fn print_histogram(stats: Stats) -> Result<(), ParseError> {
+    let mut histogram: Vec<u64> = vec![];
+    let mut found_histogram = false;
+    for stat in stats {
+        let stat = stat?;
+        if let Some(stat_histogram) = stat.size_histogram {
+            found_histogram = true;
+            if histogram.len() < stat_histogram.len() {
+                histogram.resize(stat_histogram.len(), 0);
+            }
+            for (bucket, count) in stat_histogram.into_iter().enumerate() {
+                histogram[bucket] += count;
+            }
+        }
+    }
+
+    if !found_histogram {
+        eprintln!(
+            "The input file doesn't contain any histograms. Scan with --histogram to include them."
+        );
+        std::process::exit(1);
+    }
+
+    let total: u64 = histogram.iter().sum();
+    let largest = histogram.iter().copied().max().unwrap_or(0).max(1);
+
+    let mut table = Table::new();
+    table.set_format(*prettytable::format::consts::FORMAT_CLEAN);
+    table.set_titles(row!["Size", "Files", "Percent", ""]);
+    for (bucket, count) in histogram.into_iter().enumerate() {
+        let size = if bucket == 0 {
+            "0B".to_string()
+        } else {
+            format!(
+                "{} - {}",
+                HumanBytes(1 << (bucket - 1)),
+                HumanBytes(1u64.checked_shl(bucket as u32).unwrap_or(u64::MAX))
+            )
+        };
+        let percent = count as f64 / total.max(1) as f64 * 100.0;
+        let bar = "#".repeat((count * 40 / largest) as usize);
+        table.add_row(row![size, count, format!("{:.1}%", percent), bar]);
+    }
+
+    table.printstd();
+    Ok(())
+}
+
+// Every file in the lists of largest files is shown unless given a limit.
+fn print_largest_files(stats: Stats, limit: Option<usize>) -> Result<(), ParseError> {
+    let mut largest_files: Vec<FileStat> = vec![];
+    let mut found_largest_files = false;
+    let limit = limit.unwrap_or(usize::MAX);
+    for stat in stats {
+        let stat = stat?;
+        if let Some(stat_largest_files) = stat.largest_files {
+            found_largest_files = true;
+            for file in stat_largest_files {
+                add_largest_file(&mut largest_files, limit, file.size, || file.path);
+            }
+        }
+    }
+
+    if !found_largest_files {
+        eprintln!("The input file doesn't contain any largest files. Scan with --largest-files to include them.");
+        std::process::exit(1);
+    }
+
+    largest_files_table(&largest_files).printstd();
+    Ok(())
+}
+
+fn largest_files_table(largest_files: &[FileStat]) -> Table {
+    let mut table = Table::new();
+    table.set_format(*prettytable::format::consts::FORMAT_CLEAN);
+    table.set_titles(row!["Largest Files", "Size"]);
+    for file in largest_files {
+        table.add_row(row![file.path.display(), HumanBytes(file.size)]);
+    }
+    table
+}
+
+// The --format option if given, otherwise the format detected from the input file.
+fn input_format(format: Option<Format>, input: &Path) -> Format {
+    if let Some(format) = format {
+        return format;
+    }
+    match Format::detect(input) {
+        Some(format) => format,
+        None => {
+            eprintln!(
+                "Couldn't detect the format of {}, pass it with --format",
+                input.display()
+            );
+            std::process::exit(1);
+        }
+    }
+}
+
+fn print_metadata(input: &Path, format: &Format) -> Result<(), ParseError> {
+    let metadata = match format.read_metadata(input)? {
+        Some(metadata) => metadata,
+        None => {
+            eprintln!("The input file doesn't have a metadata header. It was written by an older version of dirscan, or not by a scan.");
+            std::process::exit(1);
+        }
+    };
+    metadata.check_version(input);
+    let optional_time = |time: Option<chrono::DateTime<chrono::Utc>>| {
+        time.map(|time| time.to_rfc3339())
+            .unwrap_or_else(|| "-".to_string())
+    };
+    let roots: Vec<_> = metadata
+        .roots
+        .iter()
+        .map(|root| root.display().to_string())
+        .collect();
+    let mut table = Table::new();
+    table.set_format(*prettytable::format::consts::FORMAT_CLEAN);
+    table.add_row(row!["Schema version", metadata.schema_version]);
+    table.add_row(row!["dirscan version", metadata.dirscan_version]);
+    table.add_row(row!["Roots", roots.join("\n")]);
+    table.add_row(row!["Started", metadata.started.to_rfc3339()]);
+    table.add_row(row!["Finished", optional_time(metadata.finished)]);
+    table.add_row(row!["Partial", metadata.partial]);
+    let options = &metadata.options;
+    table.add_row(row!["Actual size", options.actual_size]);
+    table.add_row(row!["Ignore hidden", options.ignore_hidden]);
+    table.add_row(row!["One file system", options.one_file_system]);
+    table.add_row(row![
+        "Depth",
+        options
+            .depth
+            .map(|depth| depth.to_string())
+            .unwrap_or_else(|| "-".to_string())
+    ]);
+    table.add_row(row!["Arguments", metadata.arguments.join(" ")]);
+    table.printstd();
+    Ok(())
+}
+
+// SQLite databases are written to directly, rather than through get_output_file.
+fn get_writer<R: Record>(
+    format: &Format,
+    output: Option<PathBuf>,
+    compress: Option<Compression>,
+) -> Box<dyn FormatWriter<R>> {
+    let compression = Compression::for_output(compress, &output);
+    if compression != Compression::None && !format.is_compressible() {
+        eprintln!("Output in {} format can't be compressed", format);
+        std::process::exit(1);
+    }
+    match (format, output) {
+        (Format::Sqlite, Some(path)) => {
+            Box::new(SqliteWriter::create(&path).expect("Error creating the output database"))
+        }
+        (Format::Sqlite, None) => {
+            eprintln!("SQLite output must be written to a file with --output");
+            std::process::exit(1);
+        }
+        (format, output) => {
+            exit_on_write_error(format.get_writer(get_output_file(output, compression)))
+        }
+    }
+}
+
+fn get_output_file(path: Option<PathBuf>, compression: Compression) -> Encoder {
     match path {
-        None => Box::new(io::stdout()),
-        Some(buf) => Box::new(BufWriter::with_capacity(
+        None => compression.wrap_writer(io::stdout()),
+        Some(buf) => compression.wrap_writer(BufWriter::with_capacity(
             1024 * 1024,
             File::create(buf).expect("Error opening the output file"),
         )),
     }
 }
 
+// Stop the scan on the first Ctrl-C or SIGTERM, as no more directories are read and the walk ends
+// at the next entry. Exit immediately on the second in case writing out the results is taking
+// too long.
+fn handle_interrupts(interrupted: Arc<AtomicBool>) {
+    ctrlc::set_handler(move || {
+        if interrupted.swap(true, Ordering::SeqCst) {
+            std::process::exit(INTERRUPTED_EXIT_CODE);
+        }
+        eprintln!("Stopping the scan, press Ctrl-C again to exit immediately");
+    })
+    .expect("Error setting signal handler");
+}
+
 pub fn reset_signal_pipe_handler() -> io::Result<()> {
     #[cfg(target_family = "unix")]
     {
@@ -264,9 +1173,78 @@ pub fn reset_signal_pipe_handler() -> io::Result<()> {
 
         unsafe {
             signal::signal(signal::Signal::SIGPIPE, signal::SigHandler::SigDfl)
-                .map_err(|e| io::Error::new(ErrorKind::Other, e))?;
+                .map_err(io::Error::other)?;
         }
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+    use std::fs;
+
+    fn arg(path: &Path) -> &str {
+        path.to_str().unwrap()
+    }
+
+    #[test]
+    fn an_interrupted_walk_writes_partial_output() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("root");
+        for (path, size) in [("f1", 10), ("a/f2", 20), ("a/b/f3", 30), ("c/f4", 40)] {
+            let path = root.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, vec![b'x'; size]).unwrap();
+        }
+        let output = dir.path().join("output.json");
+        let mut writer = get_writer(&Format::Json, Some(output.clone()), None);
+        let metadata = ScanMetadata::new(vec![root.clone()]);
+        writer.write_metadata(&metadata).unwrap();
+        let walk_state = WalkState::new(writer, None).metadata(Some(metadata));
+        let error_log = ErrorLog::new(None).unwrap();
+
+        // Interrupted as the walk reaches a/b, once root and a have been started.
+        let interrupted = Arc::new(AtomicBool::new(false));
+        let walker =
+            Walker::new(2, false, false, true, true).interrupted(Some(interrupted.clone()));
+        let walk_root = |root: &PathBuf| {
+            let stop_at = root.join("a/b");
+            let interrupted = interrupted.clone();
+            Some(walker.walk_dir(root).inspect(move |entry| {
+                if entry.as_ref().is_ok_and(|entry| entry.path() == stop_at) {
+                    interrupted.store(true, Ordering::SeqCst);
+                }
+            }))
+        };
+        let status = write_walks(
+            vec![root.clone()],
+            walk_root,
+            walk_state,
+            &error_log,
+            &interrupted,
+        );
+        assert_eq!(status, INTERRUPTED_EXIT_CODE);
+
+        let lines: Vec<Value> = fs::read_to_string(&output)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.last().unwrap()["metadata"]["partial"], true);
+        let records: Vec<_> = lines[1..lines.len() - 1]
+            .iter()
+            .map(|record| {
+                (
+                    record["path"].as_str().unwrap(),
+                    record["incomplete"] == true,
+                )
+            })
+            .collect();
+        // Only the directories that were being walked are written, as neither is complete.
+        let a = root.join("a");
+        assert_eq!(records, [(arg(&a), true), (arg(&root), true)]);
+    }
+}
@@ -0,0 +1,155 @@
+use crate::directory_stat::DirectoryStat;
+use crate::formats::{ParseError, Stats};
+use std::cmp::Ordering;
+use std::iter::Peekable;
+use std::path::{Path, PathBuf};
+
+// The order that scans write directories in: every directory comes after everything beneath it,
+// and otherwise directories are ordered by name.
+pub fn scan_order(a: &Path, b: &Path) -> Ordering {
+    if a == b {
+        Ordering::Equal
+    } else if a.starts_with(b) {
+        Ordering::Less
+    } else if b.starts_with(a) {
+        Ordering::Greater
+    } else {
+        a.cmp(b)
+    }
+}
+
+// Merges results files that are each in scan order into a single stream, combining directories
+// that appear in more than one of them. Errors are given with the input they came from, including
+// an input that turns out not to be in scan order, and end the merge.
+pub struct MergedStats {
+    inputs: Vec<(PathBuf, Peekable<Stats>)>,
+    // The number of largest files to keep for each directory, if it's known.
+    largest_files: Option<usize>,
+}
+
+impl MergedStats {
+    pub fn new(inputs: Vec<(PathBuf, Stats)>, largest_files: Option<usize>) -> MergedStats {
+        MergedStats {
+            largest_files,
+            inputs: inputs
+                .into_iter()
+                .map(|(path, stats)| (path, stats.peekable()))
+                .collect(),
+        }
+    }
+
+    // Only called once the next entry of the input is known to be a directory.
+    fn next_from(&mut self, index: usize) -> Result<DirectoryStat, (PathBuf, ParseError)> {
+        let (input, stats) = &mut self.inputs[index];
+        let stat = match stats.next() {
+            Some(Ok(stat)) => stat,
+            _ => unreachable!("the next entry was peeked"),
+        };
+        if let Some(Ok(next)) = stats.peek() {
+            if scan_order(&next.path, &stat.path) != Ordering::Greater {
+                let message = format!(
+                    "not in the order written by a scan: {} comes after {}",
+                    next.path.display(),
+                    stat.path.display()
+                );
+                return Err((input.clone(), ParseError::InvalidFile(message)));
+            }
+        }
+        Ok(stat)
+    }
+}
+
+impl Iterator for MergedStats {
+    type Item = Result<DirectoryStat, (PathBuf, ParseError)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for (input, stats) in &mut self.inputs {
+            if let Some(Err(_)) = stats.peek() {
+                if let Some(Err(error)) = stats.next() {
+                    return Some(Err((input.clone(), error)));
+                }
+            }
+        }
+
+        let mut first: Option<(usize, &Path)> = None;
+        for (index, (_input, stats)) in self.inputs.iter_mut().enumerate() {
+            if let Some(Ok(stat)) = stats.peek() {
+                match first {
+                    Some((_, path)) if scan_order(&stat.path, path) != Ordering::Less => {}
+                    _ => first = Some((index, &stat.path)),
+                }
+            }
+        }
+        let (first, path) = first?;
+        let path = path.to_path_buf();
+
+        let mut merged = match self.next_from(first) {
+            Ok(stat) => stat,
+            Err(error) => return Some(Err(error)),
+        };
+        for index in first + 1..self.inputs.len() {
+            if matches!(self.inputs[index].1.peek(), Some(Ok(stat)) if stat.path == path) {
+                let stat = match self.next_from(index) {
+                    Ok(stat) => stat,
+                    Err(error) => return Some(Err(error)),
+                };
+                // The same directory in another scan, so its subtree is combined too.
+                merged.merge(&stat, self.largest_files);
+                merged.merge_recursive(&stat);
+            }
+        }
+        Some(Ok(merged))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::directory_stat::tests::stat;
+
+    fn input(name: &str, paths: &[&str]) -> (PathBuf, Stats) {
+        let stats: Vec<_> = paths.iter().map(|path| Ok(stat(path, 10, 1))).collect();
+        (PathBuf::from(name), Box::new(stats.into_iter()))
+    }
+
+    #[test]
+    fn inputs_are_merged_in_scan_order() {
+        let merged: Vec<_> = MergedStats::new(
+            vec![
+                input("first", &["/r/a", "/r/c", "/r"]),
+                input("second", &["/r/b/d", "/r/b", "/r/c", "/r"]),
+            ],
+            None,
+        )
+        .map(|stat| stat.ok().unwrap())
+        .collect();
+        let paths: Vec<_> = merged.iter().map(|stat| stat.path.as_path()).collect();
+        let expected: Vec<_> = ["/r/a", "/r/b/d", "/r/b", "/r/c", "/r"]
+            .iter()
+            .map(Path::new)
+            .collect();
+        assert_eq!(paths, expected);
+        assert_eq!(merged[3].total_size, 20);
+        assert_eq!(merged[3].file_count, 2);
+        assert_eq!(merged[4].total_size, 20);
+    }
+
+    #[test]
+    fn inputs_out_of_scan_order_are_an_error() {
+        let mut merged = MergedStats::new(
+            vec![
+                input("first", &["/r/a", "/r"]),
+                input("second", &["/r", "/r/b"]),
+            ],
+            None,
+        );
+        assert!(matches!(merged.next(), Some(Ok(stat)) if stat.path == Path::new("/r/a")));
+        match merged.next() {
+            Some(Err((input, ParseError::InvalidFile(message)))) => {
+                assert_eq!(input, Path::new("second"));
+                assert!(message.contains("/r/b comes after /r"), "{}", message);
+            }
+            _ => panic!("Expected an error for the second input"),
+        }
+    }
+}
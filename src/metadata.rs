@@ -0,0 +1,230 @@
+use crate::options::ScanOptions;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+// Increased whenever the records written for each directory change in a way older versions
+// can't read.
+pub const SCHEMA_VERSION: u32 = 1;
+
+// Describes how a results file was produced.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ScanMetadata {
+    // Files written before the schema was versioned have no header, which is version 0.
+    #[serde(default)]
+    pub schema_version: u32,
+    #[serde(default)]
+    pub dirscan_version: String,
+    pub roots: Vec<PathBuf>,
+    pub started: DateTime<Utc>,
+    // Only known once the scan has completed.
+    pub finished: Option<DateTime<Utc>>,
+    // The scan was interrupted, so directories are missing and the totals of those that were
+    // being walked are a lower bound.
+    #[serde(default)]
+    pub partial: bool,
+    #[serde(flatten)]
+    pub options: ScanOptions,
+    // The full command line, which includes any other options.
+    pub arguments: Vec<String>,
+}
+
+impl ScanMetadata {
+    pub fn new(roots: Vec<PathBuf>) -> Self {
+        ScanMetadata {
+            schema_version: SCHEMA_VERSION,
+            dirscan_version: env!("CARGO_PKG_VERSION").to_string(),
+            roots,
+            started: Utc::now(),
+            finished: None,
+            partial: false,
+            options: ScanOptions::default(),
+            arguments: std::env::args().collect(),
+        }
+    }
+
+    // Newer schemas may have changed the meaning of fields, so the results could be wrong.
+    pub fn check_version(&self, path: &Path) {
+        if self.schema_version > SCHEMA_VERSION {
+            eprintln!(
+                "Warning: {} was written by dirscan {} using schema version {}, but this version only supports up to {}. Some values may be missing or wrong.",
+                path.display(),
+                self.dirscan_version,
+                self.schema_version,
+                SCHEMA_VERSION
+            );
+        }
+    }
+
+    pub fn check_partial(&self, path: &Path) {
+        if self.partial {
+            eprintln!(
+                "Warning: {} is from a scan that was interrupted. Some directories are missing, and the totals of others are a lower bound.",
+                path.display()
+            );
+        }
+    }
+}
+
+// The record written at the start and end of JSON output, and as a comment in CSV output. The
+// record at the end includes when the scan finished, or that it was interrupted.
+#[derive(Serialize, Deserialize)]
+pub struct MetadataRecord {
+    pub metadata: ScanMetadata,
+}
+
+// How far from the end of a file to look for the record written when the scan finished.
+const TRAILER_SEARCH_LENGTH: u64 = 64 * 1024;
+
+const JSON_HEADER: &str = "{\"metadata\":";
+const CSV_HEADER: &str = "#";
+
+pub fn json_header(metadata: &ScanMetadata) -> String {
+    let record = MetadataRecord {
+        metadata: metadata.clone(),
+    };
+    serde_json::to_string(&record).expect("Error serializing metadata")
+}
+
+pub fn csv_header(metadata: &ScanMetadata) -> String {
+    format!("{}{}", CSV_HEADER, json_header(metadata))
+}
+
+pub fn is_json_header(line: &str) -> bool {
+    line.starts_with(JSON_HEADER)
+}
+
+// The record at the end of uncompressed JSON or CSV output, if the scan got as far as writing it.
+pub fn read_trailer(path: &Path) -> io::Result<Option<ScanMetadata>> {
+    let mut file = File::open(path)?;
+    let length = file.metadata()?.len();
+    file.seek(SeekFrom::Start(
+        length.saturating_sub(TRAILER_SEARCH_LENGTH),
+    ))?;
+    let mut end = vec![];
+    file.read_to_end(&mut end)?;
+    let end = String::from_utf8_lossy(&end);
+    Ok(end
+        .lines()
+        .rev()
+        .find(|line| !line.trim().is_empty())
+        .and_then(|line| parse_header(line.trim_end())))
+}
+
+// Could this be the start of a line written by `json_header` or `csv_header`?
+fn could_be_header(line: &[u8]) -> bool {
+    let line = line.strip_prefix(CSV_HEADER.as_bytes()).unwrap_or(line);
+    let prefix = JSON_HEADER.as_bytes();
+    line.starts_with(prefix) || prefix.starts_with(line)
+}
+
+// Passes JSON or CSV output through unchanged, keeping the last metadata record in it. This
+// finds the record written when the scan finished as the records are read, so compressed output
+// doesn't have to be decompressed twice.
+pub struct MetadataObserver<R> {
+    inner: R,
+    // The current line, for as long as it could be a metadata record.
+    line: Vec<u8>,
+    skipping: bool,
+    last: Rc<RefCell<Option<ScanMetadata>>>,
+}
+
+impl<R: Read> MetadataObserver<R> {
+    pub fn new(inner: R) -> (MetadataObserver<R>, Rc<RefCell<Option<ScanMetadata>>>) {
+        let last = Rc::new(RefCell::new(None));
+        let observer = MetadataObserver {
+            inner,
+            line: vec![],
+            skipping: false,
+            last: last.clone(),
+        };
+        (observer, last)
+    }
+
+    fn observe(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            let (part, rest, line_end) = match data.iter().position(|byte| *byte == b'\n') {
+                Some(end) => (&data[..end], &data[end + 1..], true),
+                None => (data, &data[data.len()..], false),
+            };
+            if !self.skipping {
+                self.line.extend_from_slice(part);
+                if !could_be_header(&self.line) {
+                    self.skipping = true;
+                    self.line.clear();
+                }
+            }
+            if line_end {
+                if !self.skipping {
+                    let line = String::from_utf8_lossy(&self.line);
+                    if let Some(metadata) = parse_header(line.trim_end()) {
+                        self.last.replace(Some(metadata));
+                    }
+                }
+                self.line.clear();
+                self.skipping = false;
+            }
+            data = rest;
+        }
+    }
+}
+
+impl<R: Read> Read for MetadataObserver<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let length = self.inner.read(buf)?;
+        self.observe(&buf[..length]);
+        Ok(length)
+    }
+}
+
+// Parse a header line written by `json_header` or `csv_header`.
+pub fn parse_header(line: &str) -> Option<ScanMetadata> {
+    let line = line.strip_prefix(CSV_HEADER).unwrap_or(line);
+    if !is_json_header(line) {
+        return None;
+    }
+    match serde_json::from_str::<MetadataRecord>(line) {
+        Ok(record) => Some(record.metadata),
+        Err(e) => {
+            eprintln!("Warning: ignoring invalid metadata header: {}", e);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn observer_keeps_the_last_metadata_record() {
+        let header = ScanMetadata::new(vec![PathBuf::from("/a")]);
+        let trailer = ScanMetadata {
+            partial: true,
+            ..header.clone()
+        };
+        let contents = format!(
+            "{}\n{{\"path\":\"/a\"}}\n{}\n",
+            csv_header(&header),
+            csv_header(&trailer)
+        );
+
+        let (mut observer, last) = MetadataObserver::new(contents.as_bytes());
+        // Read a few bytes at a time, so that records are split across reads.
+        let mut read = vec![];
+        let mut buffer = [0; 7];
+        loop {
+            let length = observer.read(&mut buffer).unwrap();
+            if length == 0 {
+                break;
+            }
+            read.extend_from_slice(&buffer[..length]);
+        }
+        assert_eq!(read, contents.as_bytes());
+        assert!(last.take().unwrap().partial);
+    }
+}
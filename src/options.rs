@@ -0,0 +1,46 @@
+use serde::{Deserialize, Serialize};
+
+// The options that change what a scan records. A scan is only resumed from a checkpoint, or
+// reuses the directories of a baseline scan, if they were recorded with the same options.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Default)]
+#[serde(default)]
+pub struct ScanOptions {
+    pub actual_size: bool,
+    pub ignore_hidden: bool,
+    pub one_file_system: bool,
+    pub depth: Option<usize>,
+    // The include and exclude filters as given on the command line, such as `--include=*.log`.
+    pub filters: Vec<String>,
+    pub extensions: bool,
+    pub owners: bool,
+    pub histogram: bool,
+    pub age_buckets: Vec<u32>,
+    // The number of largest files recorded in each directory.
+    pub largest_files: Option<usize>,
+}
+
+impl ScanOptions {
+    // The command line options that are set differently in `other`.
+    pub fn differences(&self, other: &ScanOptions) -> Vec<&'static str> {
+        let options = [
+            ("--actual-size", self.actual_size == other.actual_size),
+            ("--ignore-hidden", self.ignore_hidden == other.ignore_hidden),
+            (
+                "--one-file-system",
+                self.one_file_system == other.one_file_system,
+            ),
+            ("--depth", self.depth == other.depth),
+            ("the filters", self.filters == other.filters),
+            ("--extensions", self.extensions == other.extensions),
+            ("--owners", self.owners == other.owners),
+            ("--histogram", self.histogram == other.histogram),
+            ("--age-buckets", self.age_buckets == other.age_buckets),
+            ("--largest-files", self.largest_files == other.largest_files),
+        ];
+        options
+            .iter()
+            .filter(|(_, same)| !same)
+            .map(|(option, _)| *option)
+            .collect()
+    }
+}
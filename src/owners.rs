@@ -0,0 +1,53 @@
+use std::collections::HashMap;
+
+// Resolves uids and gids to names using the local passwd and group databases, falling back to
+// the numeric ID if there is no entry.
+#[derive(Default)]
+pub struct NameCache {
+    users: HashMap<u32, String>,
+    groups: HashMap<u32, String>,
+}
+
+impl NameCache {
+    pub fn user(&mut self, uid: u32) -> String {
+        self.users
+            .entry(uid)
+            .or_insert_with(|| lookup_user(uid).unwrap_or_else(|| uid.to_string()))
+            .clone()
+    }
+
+    pub fn group(&mut self, gid: u32) -> String {
+        self.groups
+            .entry(gid)
+            .or_insert_with(|| lookup_group(gid).unwrap_or_else(|| gid.to_string()))
+            .clone()
+    }
+}
+
+#[cfg(target_family = "unix")]
+fn lookup_user(uid: u32) -> Option<String> {
+    use nix::unistd::{Uid, User};
+    User::from_uid(Uid::from_raw(uid))
+        .ok()
+        .flatten()
+        .map(|user| user.name)
+}
+
+#[cfg(target_family = "unix")]
+fn lookup_group(gid: u32) -> Option<String> {
+    use nix::unistd::{Gid, Group};
+    Group::from_gid(Gid::from_raw(gid))
+        .ok()
+        .flatten()
+        .map(|group| group.name)
+}
+
+#[cfg(not(target_family = "unix"))]
+fn lookup_user(_uid: u32) -> Option<String> {
+    None
+}
+
+#[cfg(not(target_family = "unix"))]
+fn lookup_group(_gid: u32) -> Option<String> {
+    None
+}
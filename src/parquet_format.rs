@@ -0,0 +1,297 @@
+use crate::columns::{self, ColumnType, Columns, Record};
+use crate::formats::{FormatWriter, ParseError, Stats};
+use crate::metadata::ScanMetadata;
+use arrow_array::builder::{
+    ArrayBuilder, BooleanBuilder, StringBuilder, TimestampNanosecondBuilder, UInt64Builder,
+};
+use arrow_array::cast::AsArray;
+use arrow_array::types::{TimestampNanosecondType, UInt64Type};
+use arrow_array::{Array, RecordBatch};
+use arrow_schema::{DataType, Field, Schema, TimeUnit};
+use chrono::{DateTime, TimeZone, Utc};
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::metadata::KeyValue;
+use parquet::file::properties::WriterProperties;
+use serde_json::{Map, Value};
+use std::fs::File;
+use std::io;
+use std::sync::Arc;
+
+// The number of records converted into columns at a time.
+const BATCH_SIZE: usize = 8192;
+const ROW_GROUP_SIZE: usize = 128 * 1024;
+// The key of the scan metadata in the file's key-value metadata.
+const METADATA_KEY: &str = "dirscan";
+
+fn schema(columns: Columns) -> Schema {
+    Schema::new(
+        columns
+            .iter()
+            .map(|(name, column_type)| {
+                let data_type = match column_type {
+                    ColumnType::Integer => DataType::UInt64,
+                    ColumnType::Boolean => DataType::Boolean,
+                    ColumnType::Text | ColumnType::Json => DataType::Utf8,
+                    ColumnType::Timestamp => {
+                        DataType::Timestamp(TimeUnit::Nanosecond, Some("UTC".into()))
+                    }
+                };
+                Field::new(*name, data_type, true)
+            })
+            .collect::<Vec<_>>(),
+    )
+}
+
+// Records are buffered and written in batches, as a row group at a time. Nothing is readable
+// until the writer has been finished, which writes the file footer.
+pub struct ParquetWriter {
+    output: Option<Box<dyn io::Write + Send>>,
+    writer: Option<ArrowWriter<Box<dyn io::Write + Send>>>,
+    columns: Columns,
+    rows: Vec<Vec<Value>>,
+    metadata: Option<ScanMetadata>,
+}
+
+impl<R: Record> FormatWriter<R> for ParquetWriter {
+    // Kept until the footer is written, replacing any written earlier.
+    fn write_metadata(&mut self, metadata: &ScanMetadata) -> io::Result<()> {
+        self.metadata = Some(metadata.clone());
+        Ok(())
+    }
+
+    fn write_record(&mut self, record: &R) -> io::Result<()> {
+        self.rows.push(columns::to_row(record)?);
+        if self.rows.len() >= BATCH_SIZE {
+            self.write_batch()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_batch()?;
+        if let Some(writer) = &mut self.writer {
+            writer.flush()?;
+        }
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> io::Result<()> {
+        self.write_batch()?;
+        if let Some(mut writer) = self.writer.take() {
+            if let Some(metadata) = self.metadata.take() {
+                let value = serde_json::to_string(&metadata)?;
+                writer.append_key_value_metadata(KeyValue::new(METADATA_KEY.to_string(), value));
+            }
+            writer.close()?;
+        }
+        Ok(())
+    }
+}
+
+impl ParquetWriter {
+    pub fn new(writer: Box<dyn io::Write + Send>, columns: Columns) -> Self {
+        ParquetWriter {
+            output: Some(writer),
+            writer: None,
+            columns,
+            rows: vec![],
+            metadata: None,
+        }
+    }
+
+    fn write_batch(&mut self) -> io::Result<()> {
+        if self.writer.is_none() {
+            let properties = WriterProperties::builder()
+                .set_compression(Compression::SNAPPY)
+                .set_max_row_group_size(ROW_GROUP_SIZE)
+                .build();
+            self.writer = Some(ArrowWriter::try_new(
+                self.output.take().unwrap(),
+                Arc::new(schema(self.columns)),
+                Some(properties),
+            )?);
+        }
+        if self.rows.is_empty() {
+            return Ok(());
+        }
+        let rows = std::mem::take(&mut self.rows);
+        let arrays = self
+            .columns
+            .iter()
+            .enumerate()
+            .map(|(index, (_, column_type))| {
+                build_array(*column_type, rows.iter().map(|row| &row[index]))
+            })
+            .collect();
+        let batch = RecordBatch::try_new(Arc::new(schema(self.columns)), arrays)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        self.writer.as_mut().unwrap().write(&batch)?;
+        Ok(())
+    }
+}
+
+fn build_array<'a>(
+    column_type: ColumnType,
+    values: impl Iterator<Item = &'a Value>,
+) -> Arc<dyn Array> {
+    match column_type {
+        ColumnType::Integer => {
+            let mut builder = UInt64Builder::new();
+            values.for_each(|value| builder.append_option(value.as_u64()));
+            finish(builder)
+        }
+        ColumnType::Boolean => {
+            let mut builder = BooleanBuilder::new();
+            values.for_each(|value| builder.append_option(value.as_bool()));
+            finish(builder)
+        }
+        ColumnType::Text => {
+            let mut builder = StringBuilder::new();
+            values.for_each(|value| builder.append_option(value.as_str()));
+            finish(builder)
+        }
+        ColumnType::Timestamp => {
+            let mut builder = TimestampNanosecondBuilder::new().with_timezone("UTC");
+            values.for_each(|value| {
+                let time = value
+                    .as_str()
+                    .and_then(|time| DateTime::parse_from_rfc3339(time).ok());
+                builder.append_option(time.and_then(|time| time.timestamp_nanos_opt()))
+            });
+            finish(builder)
+        }
+        ColumnType::Json => {
+            let mut builder = StringBuilder::new();
+            values.for_each(|value| match value {
+                Value::Null => builder.append_null(),
+                other => builder.append_value(other.to_string()),
+            });
+            finish(builder)
+        }
+    }
+}
+
+fn finish(mut builder: impl ArrayBuilder) -> Arc<dyn Array> {
+    builder.finish()
+}
+
+fn invalid_file(error: impl std::fmt::Display) -> ParseError {
+    ParseError::InvalidFile(format!("Invalid parquet file: {}", error))
+}
+
+// Read each row back into a JSON object, so that records are deserialized in the same way as
+// the other formats. Timestamps become RFC 3339 strings and nested values are left as JSON text.
+pub fn parse_file(file: File) -> Result<Stats, ParseError> {
+    let reader = ParquetRecordBatchReaderBuilder::try_new(file)
+        .and_then(|builder| builder.build())
+        .map_err(invalid_file)?;
+    let mut rows_read = 0;
+    Ok(Box::new(reader.flat_map(move |batch| {
+        let batch = match batch {
+            Ok(batch) => batch,
+            Err(e) => return vec![Err(invalid_file(e))],
+        };
+        let first_row = rows_read;
+        rows_read += batch.num_rows();
+        (0..batch.num_rows())
+            .map(|row| {
+                let fields: Map<String, Value> = batch
+                    .schema()
+                    .fields()
+                    .iter()
+                    .zip(batch.columns())
+                    .map(|(field, column)| (field.name().clone(), read_value(column, row)))
+                    .collect();
+                serde_json::from_value(Value::Object(fields)).map_err(|e| {
+                    ParseError::InvalidRecord {
+                        location: format!("row {}", first_row + row + 1),
+                        message: e.to_string(),
+                    }
+                })
+            })
+            .collect::<Vec<_>>()
+    })))
+}
+
+pub fn read_metadata(file: File) -> Result<Option<ScanMetadata>, ParseError> {
+    let reader = ParquetRecordBatchReaderBuilder::try_new(file).map_err(invalid_file)?;
+    let value = reader
+        .metadata()
+        .file_metadata()
+        .key_value_metadata()
+        .and_then(|metadata| metadata.iter().find(|kv| kv.key == METADATA_KEY))
+        .and_then(|kv| kv.value.as_ref());
+    Ok(value.and_then(|value| serde_json::from_str(value).ok()))
+}
+
+fn read_value(column: &Arc<dyn Array>, row: usize) -> Value {
+    if column.is_null(row) {
+        return Value::Null;
+    }
+    match column.data_type() {
+        DataType::UInt64 => column.as_primitive::<UInt64Type>().value(row).into(),
+        DataType::Boolean => column.as_boolean().value(row).into(),
+        DataType::Utf8 => column.as_string::<i32>().value(row).into(),
+        DataType::Timestamp(TimeUnit::Nanosecond, _) => {
+            let nanos = column.as_primitive::<TimestampNanosecondType>().value(row);
+            Utc.timestamp_nanos(nanos).to_rfc3339().into()
+        }
+        _ => Value::Null,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compression;
+    use crate::directory_stat::tests::stat;
+    use crate::directory_stat::{BreakdownStat, DirectoryStat};
+    use crate::formats::Format;
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn stats_and_metadata_can_be_read_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("output.parquet");
+        let mut with_extensions = stat("/a", 20, 2);
+        let mut extensions = std::collections::BTreeMap::new();
+        extensions.insert(
+            "txt".to_string(),
+            BreakdownStat {
+                file_count: 2,
+                total_size: 20,
+            },
+        );
+        with_extensions.extensions = Some(extensions);
+        with_extensions.latest_modified = Some(Utc::now());
+        with_extensions.reused = true;
+        let metadata = ScanMetadata::new(vec![PathBuf::from("/a")]);
+
+        let mut writer: Box<dyn FormatWriter<DirectoryStat>> = Format::Parquet
+            .get_writer(compression::Compression::None.wrap_writer(File::create(&path).unwrap()))
+            .unwrap();
+        writer.write_metadata(&metadata).unwrap();
+        writer.write_record(&stat("/a/b", 10, 1)).unwrap();
+        writer.write_record(&with_extensions).unwrap();
+        writer.finish().unwrap();
+
+        let stats: Vec<_> = parse_file(File::open(&path).unwrap())
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(stats.len(), 2);
+        assert_eq!(stats[0].path, Path::new("/a/b"));
+        assert!(stats[0].extensions.is_none());
+        assert!(!stats[0].reused);
+        assert_eq!(stats[1].total_size, 20);
+        assert_eq!(stats[1].extensions.as_ref().unwrap()["txt"].total_size, 20);
+        assert_eq!(stats[1].latest_modified, with_extensions.latest_modified);
+        assert!(stats[1].reused);
+
+        let read = read_metadata(File::open(&path).unwrap()).unwrap().unwrap();
+        assert_eq!(read.roots, metadata.roots);
+        assert_eq!(read.started, metadata.started);
+    }
+}
@@ -3,33 +3,48 @@ use console::style;
 use indicatif::{HumanBytes, ProgressBar, ProgressStyle};
 use jwalk::DirEntry;
 
-use prettytable::{cell, row, table};
+use prettytable::{row, table, Table};
+use std::collections::BTreeMap;
 use std::fmt;
 use std::path::PathBuf;
 use std::time::{Duration, Instant};
 
-pub struct WalkProgress {
+#[derive(Default)]
+struct RootProgress {
+    root: PathBuf,
     errors: u64,
     total: u64,
     total_size: u64,
+}
+
+pub struct WalkProgress {
+    // Totals for each root being walked, in the order they are walked.
+    roots: Vec<RootProgress>,
+    current: usize,
+    // The number of errors of each kind, once the walk has finished.
+    error_kinds: BTreeMap<String, u64>,
 
-    root: PathBuf,
     update_frequency: Duration,
     started: Instant,
     last_update: Instant,
 }
 
 impl WalkProgress {
-    pub fn new(root: PathBuf) -> WalkProgress {
+    pub fn new(roots: Vec<PathBuf>) -> WalkProgress {
         let update_frequency = Duration::from_millis(500);
         let started = Instant::now();
 
         WalkProgress {
-            errors: 0,
-            total: 0,
-            total_size: 0,
+            roots: roots
+                .into_iter()
+                .map(|root| RootProgress {
+                    root,
+                    ..Default::default()
+                })
+                .collect(),
+            current: 0,
+            error_kinds: BTreeMap::new(),
 
-            root,
             update_frequency,
             started,
             last_update: started,
@@ -49,30 +64,67 @@ impl WalkProgress {
         self.last_update.elapsed() > self.update_frequency
     }
 
+    // Record any further progress against the root at this index.
+    pub fn start_root(&mut self, index: usize) {
+        self.current = index;
+    }
+
+    pub fn set_error_kinds(&mut self, error_kinds: BTreeMap<String, u64>) {
+        self.error_kinds = error_kinds;
+    }
+
+    fn total(&self) -> u64 {
+        self.roots.iter().map(|r| r.total).sum()
+    }
+
+    fn total_size(&self) -> u64 {
+        self.roots.iter().map(|r| r.total_size).sum()
+    }
+
+    fn errors(&self) -> u64 {
+        self.roots.iter().map(|r| r.errors).sum()
+    }
+
     pub fn update(&mut self, progress_bar: &ProgressBar) {
         self.last_update = Instant::now();
-        progress_bar.set_position(self.total);
-        progress_bar.set_message(
-            format!(
-                "Files: {} | Size: {} | Errors: {}",
-                style(self.total).green(),
-                style(HumanBytes(self.total_size)).green(),
-                style(self.errors).red(),
-            )
-            .as_ref(),
+        progress_bar.set_position(self.total());
+        let mut message = format!(
+            "Files: {} | Size: {} | Errors: {}",
+            style(self.total()).green(),
+            style(HumanBytes(self.total_size())).green(),
+            style(self.errors()).red(),
         );
+        if self.roots.len() > 1 {
+            let root = &self.roots[self.current];
+            message.push_str(&format!(
+                " | Root {}/{}: {} ({} files, {})",
+                self.current + 1,
+                self.roots.len(),
+                style(root.root.display()).blue(),
+                style(root.total).green(),
+                style(HumanBytes(root.total_size)).green(),
+            ));
+        }
+        progress_bar.set_message(message.as_ref());
     }
 
     pub fn record_progress(&mut self, item: &Result<DirEntry<((), ClientState)>, jwalk::Error>) {
-        self.total += 1;
+        let root = &mut self.roots[self.current];
+        root.total += 1;
         match item {
-            Err(_) => self.errors += 1,
-            Ok(dir_entry) => match &dir_entry.client_state {
-                Some(metadata) => {
-                    self.total_size += metadata.size;
+            Err(_) => root.errors += 1,
+            Ok(dir_entry) => {
+                match &dir_entry.client_state {
+                    Some(metadata) => {
+                        root.total_size += metadata.size;
+                    }
+                    None => root.errors += 1,
+                }
+                // The directory itself was read, but its contents couldn't be.
+                if dir_entry.read_children_error.is_some() {
+                    root.errors += 1;
                 }
-                None => self.errors += 1,
-            },
+            }
         }
     }
 }
@@ -85,21 +137,49 @@ impl std::fmt::Display for WalkProgress {
             chrono_humanize::Accuracy::Precise,
             chrono_humanize::Tense::Present,
         );
-        let errors = if self.errors > 0 {
-            style(self.errors).red()
-        } else {
-            style(self.errors).green()
+        let styled_errors = |errors: u64| {
+            if errors > 0 {
+                style(errors).red()
+            } else {
+                style(errors).green()
+            }
+        };
+        if self.roots.len() > 1 {
+            let mut roots_table = Table::new();
+            roots_table.set_format(*prettytable::format::consts::FORMAT_CLEAN);
+            roots_table.set_titles(row!["Root", "Total Size", "Files", "Errors"]);
+            for root in &self.roots {
+                roots_table.add_row(row![
+                    style(root.root.display()).blue(),
+                    style(HumanBytes(root.total_size)).green(),
+                    style(root.total).green(),
+                    styled_errors(root.errors),
+                ]);
+            }
+            writeln!(f, "{}", roots_table)?;
+        }
+        let root = match self.roots.as_slice() {
+            [root] => root.root.display().to_string(),
+            roots => format!("{} roots", roots.len()),
         };
         let mut table = table!(
-            ["Root", style(self.root.display()).blue()],
+            ["Root", style(root).blue()],
             [
                 "Total Size",
-                style(indicatif::HumanBytes(self.total_size)).green()
+                style(indicatif::HumanBytes(self.total_size())).green()
             ],
-            ["Files", style(self.total).green()],
+            ["Files", style(self.total()).green()],
             ["Duration", style(runtime_text).green()],
-            ["Errors", errors]
+            ["Errors", styled_errors(self.errors())]
         );
+        if !self.error_kinds.is_empty() {
+            let kinds: Vec<_> = self
+                .error_kinds
+                .iter()
+                .map(|(kind, count)| format!("{}={}", kind, count))
+                .collect();
+            table.add_row(row!["Errors by kind", style(kinds.join(" ")).red()]);
+        }
         table.set_format(*prettytable::format::consts::FORMAT_CLEAN);
         write!(f, "{}", table)
     }
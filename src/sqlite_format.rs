@@ -0,0 +1,357 @@
+use crate::columns::{self, ColumnType, Columns, Record};
+use crate::directory_stat::DirectoryStat;
+use crate::dupes::DuplicateGroup;
+use crate::formats::{FormatWriter, ParseError, Stats};
+use crate::metadata::ScanMetadata;
+use rusqlite::types::Value as SqlValue;
+use rusqlite::{params, params_from_iter, Connection, OpenFlags, Row};
+use serde_json::{Map, Value};
+use std::collections::{HashSet, VecDeque};
+use std::convert::TryFrom;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// The number of rows inserted in each transaction, and read by each query.
+const BATCH_SIZE: usize = 10_000;
+
+fn to_io_error(error: rusqlite::Error) -> io::Error {
+    io::Error::other(error)
+}
+
+fn column_names(columns: &[(&str, ColumnType)]) -> String {
+    let names: Vec<_> = columns.iter().map(|(name, _)| *name).collect();
+    names.join(", ")
+}
+
+fn create_table(table: &str, columns: Columns) -> String {
+    let definitions: Vec<_> = columns
+        .iter()
+        .map(|(name, column_type)| {
+            let sql_type = match column_type {
+                ColumnType::Integer => "INTEGER",
+                ColumnType::Boolean => "BOOLEAN",
+                ColumnType::Text | ColumnType::Json => "TEXT",
+                ColumnType::Timestamp => "TIMESTAMP",
+            };
+            format!("{} {}", name, sql_type)
+        })
+        .collect();
+    format!("CREATE TABLE {} ({});", table, definitions.join(", "))
+}
+
+fn insert(table: &str, columns: Columns) -> String {
+    let placeholders = vec!["?"; columns.len()].join(", ");
+    format!(
+        "INSERT INTO {} ({}) VALUES ({})",
+        table,
+        column_names(columns),
+        placeholders
+    )
+}
+
+// Directories are written to the `directories` table and duplicate groups to `duplicates`, with
+// timestamps as RFC 3339 text. The index on path is created once everything has been written,
+// as inserting into it as we go is much slower.
+pub struct SqliteWriter {
+    connection: Connection,
+    pending: usize,
+}
+
+impl SqliteWriter {
+    pub fn create(path: &Path) -> io::Result<SqliteWriter> {
+        // Replace any existing database, as the other formats do when creating their output.
+        match std::fs::remove_file(path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+        let connection = Connection::open(path).map_err(to_io_error)?;
+        connection
+            .execute_batch(&format!(
+                "PRAGMA journal_mode = OFF;
+                 PRAGMA synchronous = OFF;
+                 CREATE TABLE metadata (key TEXT PRIMARY KEY, value TEXT);
+                 {}
+                 {}
+                 BEGIN;",
+                create_table(DirectoryStat::TABLE, DirectoryStat::COLUMNS),
+                create_table(DuplicateGroup::TABLE, DuplicateGroup::COLUMNS),
+            ))
+            .map_err(to_io_error)?;
+        Ok(SqliteWriter {
+            connection,
+            pending: 0,
+        })
+    }
+
+    fn commit(&mut self) -> io::Result<()> {
+        self.pending = 0;
+        self.connection
+            .execute_batch("COMMIT; BEGIN;")
+            .map_err(to_io_error)
+    }
+
+    fn insert<R: Record>(&mut self, record: &R) -> io::Result<()> {
+        let row = columns::to_row(record)?;
+        // Values that don't fit the column's type are stored as NULL.
+        let values = row
+            .into_iter()
+            .zip(R::COLUMNS)
+            .map(|(value, (_, column_type))| match (value, column_type) {
+                (Value::Null, _) => SqlValue::Null,
+                (value, ColumnType::Integer) => value
+                    .as_u64()
+                    .and_then(|value| i64::try_from(value).ok())
+                    .map_or(SqlValue::Null, SqlValue::Integer),
+                (value, ColumnType::Boolean) => value
+                    .as_bool()
+                    .map_or(SqlValue::Null, |value| SqlValue::Integer(value as i64)),
+                (Value::String(string), _) => SqlValue::Text(string),
+                (value, _) => SqlValue::Text(value.to_string()),
+            });
+        self.connection
+            .prepare_cached(&insert(R::TABLE, R::COLUMNS))
+            .and_then(|mut statement| statement.execute(params_from_iter(values)))
+            .map_err(to_io_error)?;
+        self.pending += 1;
+        if self.pending >= BATCH_SIZE {
+            self.commit()?;
+        }
+        Ok(())
+    }
+}
+
+impl<R: Record> FormatWriter<R> for SqliteWriter {
+    fn write_metadata(&mut self, metadata: &ScanMetadata) -> io::Result<()> {
+        if let Value::Object(fields) = serde_json::to_value(metadata)? {
+            for (key, value) in fields {
+                let value = match value {
+                    Value::Null => None,
+                    Value::String(string) => Some(string),
+                    other => Some(other.to_string()),
+                };
+                self.connection
+                    .execute(
+                        "INSERT OR REPLACE INTO metadata (key, value) VALUES (?1, ?2)",
+                        params![key, value],
+                    )
+                    .map_err(to_io_error)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write_record(&mut self, record: &R) -> io::Result<()> {
+        self.insert(record)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.commit()
+    }
+
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        self.connection
+            .execute_batch(
+                "CREATE INDEX directories_path ON directories (path);
+                 COMMIT;",
+            )
+            .map_err(to_io_error)
+    }
+}
+
+fn invalid_file(error: rusqlite::Error) -> ParseError {
+    ParseError::InvalidFile(format!("Invalid SQLite database: {}", error))
+}
+
+// Reads directories in batches. Without a prefix they are read in the order they were written,
+// otherwise only the range of paths starting with the prefix is read using the index on path.
+struct SqliteReader {
+    connection: Connection,
+    // The columns in the database, which doesn't have those added since it was written.
+    columns: Vec<(&'static str, ColumnType)>,
+    prefix: Option<PathBuf>,
+    last_rowid: i64,
+    last_path: String,
+    stats: VecDeque<Result<DirectoryStat, ParseError>>,
+    finished: bool,
+}
+
+impl SqliteReader {
+    fn read_batch(&mut self) -> rusqlite::Result<()> {
+        let select = format!(
+            "SELECT rowid, {} FROM directories",
+            column_names(&self.columns)
+        );
+        let mut rows = vec![];
+        match &self.prefix {
+            None => {
+                let mut statement = self.connection.prepare_cached(&format!(
+                    "{} WHERE rowid > ?1 ORDER BY rowid LIMIT {}",
+                    select, BATCH_SIZE
+                ))?;
+                let mut results = statement.query(params![self.last_rowid])?;
+                while let Some(row) = results.next()? {
+                    rows.push((row.get(0)?, read_stat(row, &self.columns)?));
+                }
+            }
+            Some(prefix) => {
+                // Every path starting with the prefix sorts before the prefix followed by the
+                // largest character. This range can also include siblings of the prefix, such
+                // as `/a/b-c` for `/a/b`, which are filtered out below.
+                let start = prefix.to_string_lossy().to_string();
+                let end = format!("{}\u{10FFFF}", start);
+                let mut statement = self.connection.prepare_cached(&format!(
+                    "{} WHERE path > ?1 AND path >= ?2 AND path < ?3 ORDER BY path LIMIT {}",
+                    select, BATCH_SIZE
+                ))?;
+                let mut results = statement.query(params![self.last_path, start, end])?;
+                while let Some(row) = results.next()? {
+                    rows.push((row.get(0)?, read_stat(row, &self.columns)?));
+                }
+            }
+        }
+
+        self.finished = rows.len() < BATCH_SIZE;
+        for (rowid, (path, stat)) in rows {
+            self.last_rowid = rowid;
+            self.last_path = path;
+            let stat = stat.map_err(|e| ParseError::InvalidRecord {
+                location: format!("row {}", rowid),
+                message: e.to_string(),
+            });
+            match (&self.prefix, stat) {
+                (Some(prefix), Ok(stat)) if !stat.path.starts_with(prefix) => {}
+                (_, stat) => self.stats.push_back(stat),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Iterator for SqliteReader {
+    type Item = Result<DirectoryStat, ParseError>;
+
+    fn next(&mut self) -> Option<Result<DirectoryStat, ParseError>> {
+        while self.stats.is_empty() && !self.finished {
+            if let Err(e) = self.read_batch() {
+                self.finished = true;
+                return Some(Err(invalid_file(e)));
+            }
+        }
+        self.stats.pop_front()
+    }
+}
+
+// Convert a row back into a JSON object, so that it's deserialized in the same way as the other
+// formats. The path is returned separately so that reading can continue past invalid rows.
+fn read_stat(
+    row: &Row,
+    columns: &[(&str, ColumnType)],
+) -> rusqlite::Result<(String, serde_json::Result<DirectoryStat>)> {
+    let mut fields = Map::new();
+    for (index, (name, column_type)) in columns.iter().enumerate() {
+        let index = index + 1;
+        let value = match column_type {
+            ColumnType::Integer => row.get::<_, Option<i64>>(index)?.map(|v| (v as u64).into()),
+            ColumnType::Boolean => row.get::<_, Option<bool>>(index)?.map(Value::from),
+            _ => row.get::<_, Option<String>>(index)?.map(Value::from),
+        };
+        fields.insert(name.to_string(), value.unwrap_or(Value::Null));
+    }
+    let path = fields
+        .get("path")
+        .and_then(Value::as_str)
+        .unwrap_or_default()
+        .to_string();
+    Ok((path, serde_json::from_value(Value::Object(fields))))
+}
+
+fn open(path: &Path) -> Result<Connection, ParseError> {
+    Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY).map_err(invalid_file)
+}
+
+// Values were stored as JSON text, except for strings which were stored as they are.
+pub fn read_metadata(path: &Path) -> Result<Option<ScanMetadata>, ParseError> {
+    let connection = open(path)?;
+    let fields = connection
+        .prepare("SELECT key, value FROM metadata")
+        .and_then(|mut statement| {
+            statement
+                .query_map([], |row| {
+                    Ok((row.get::<_, String>(0)?, row.get::<_, Option<String>>(1)?))
+                })?
+                .collect::<rusqlite::Result<Vec<_>>>()
+        });
+    // Databases without a metadata table aren't an error here, as reading the directories will
+    // report any problem with the file.
+    let fields = match fields {
+        Ok(fields) => fields,
+        Err(_) => return Ok(None),
+    };
+    let fields = fields
+        .into_iter()
+        .map(|(key, value)| {
+            let value = match value {
+                None => Value::Null,
+                Some(text) => serde_json::from_str(&text).unwrap_or(Value::String(text)),
+            };
+            (key, value)
+        })
+        .collect::<Map<_, _>>();
+    Ok(serde_json::from_value(Value::Object(fields)).ok())
+}
+
+pub fn parse_file(path: &Path, prefix: &Path) -> Result<Stats, ParseError> {
+    let connection = open(path)?;
+    let existing = connection
+        .prepare("SELECT name FROM pragma_table_info('directories')")
+        .and_then(|mut statement| {
+            statement
+                .query_map([], |row| row.get::<_, String>(0))?
+                .collect::<rusqlite::Result<HashSet<_>>>()
+        })
+        .map_err(invalid_file)?;
+    let columns = DirectoryStat::COLUMNS
+        .iter()
+        .filter(|(name, _)| existing.contains(*name))
+        .copied()
+        .collect();
+    // Collecting the components removes any trailing separator from the prefix.
+    let prefix: PathBuf = prefix.components().collect();
+    let prefix = if prefix.as_os_str().is_empty() {
+        None
+    } else {
+        Some(prefix)
+    };
+    Ok(Box::new(SqliteReader {
+        connection,
+        columns,
+        prefix,
+        last_rowid: 0,
+        last_path: String::new(),
+        stats: VecDeque::new(),
+        finished: false,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::directory_stat::tests::stat;
+
+    #[test]
+    fn prefix_only_matches_paths_beneath_it() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("output.sqlite");
+        let mut writer: Box<dyn FormatWriter> = Box::new(SqliteWriter::create(&path).unwrap());
+        for stat_path in ["/x/a", "/x/b/d", "/x/b", "/x/b-c", "/x"] {
+            writer.write_record(&stat(stat_path, 10, 1)).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let paths: Vec<_> = parse_file(&path, Path::new("/x/b/"))
+            .unwrap()
+            .map(|stat| stat.ok().unwrap().path)
+            .collect();
+        assert_eq!(paths, vec![PathBuf::from("/x/b"), PathBuf::from("/x/b/d")]);
+    }
+}
@@ -1,25 +1,133 @@
-use crate::walker::MetadataWithSize;
+use crate::walker::{MetadataWithSize, WalkDirEntry};
 
+use crate::baseline::Baseline;
+use crate::checkpoint::{Checkpoint, Checkpointer};
+use crate::error_log::ErrorLog;
 use crate::formats::FormatWriter;
+use crate::metadata::ScanMetadata;
 
-use crate::directory_stat::DirectoryStat;
-use std::path::PathBuf;
+use crate::directory_stat::{add_largest_file, DirectoryStat, FileStat};
+use chrono::Utc;
+use std::collections::HashSet;
+use std::ffi::OsStr;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
 
 pub struct WalkState {
-    current: Option<DirectoryStat>,
+    // The directories that are currently being walked, from the root down to the current
+    // directory. Because the walk is depth-first, a directory is finished (and can be written
+    // out along with its recursive totals) once the walk leaves it.
+    stack: Vec<DirectoryStat>,
     writer: Box<dyn FormatWriter>,
     depth: Option<usize>,
+    // (device, inode) pairs of every hard-linked file seen so far, so that their size is
+    // only counted once.
+    seen_hard_links: HashSet<(u64, u64)>,
+    extensions: bool,
+    owners: bool,
+    histogram: bool,
+    age_buckets: Vec<u32>,
+    started: SystemTime,
+    largest_files_limit: Option<usize>,
+    // The largest files seen across the whole scan.
+    largest_files: Vec<FileStat>,
+    checkpointer: Option<Checkpointer>,
+    baseline: Option<Arc<Baseline>>,
+    error_log: Option<Arc<ErrorLog>>,
+    metadata: Option<ScanMetadata>,
 }
 
 impl WalkState {
     pub fn new(writer: Box<dyn FormatWriter>, depth: Option<usize>) -> WalkState {
         WalkState {
-            current: None,
+            stack: vec![],
             writer,
             depth,
+            seen_hard_links: HashSet::new(),
+            extensions: false,
+            owners: false,
+            histogram: false,
+            age_buckets: vec![],
+            started: SystemTime::now(),
+            largest_files_limit: None,
+            largest_files: vec![],
+            checkpointer: None,
+            baseline: None,
+            error_log: None,
+            metadata: None,
         }
     }
 
+    // Record a breakdown of file extensions for each directory.
+    pub fn extensions(mut self, extensions: bool) -> WalkState {
+        self.extensions = extensions;
+        self
+    }
+
+    // Record a breakdown of file owners and groups for each directory.
+    pub fn owners(mut self, owners: bool) -> WalkState {
+        self.owners = owners;
+        self
+    }
+
+    // Record a histogram of file sizes for each directory.
+    pub fn histogram(mut self, histogram: bool) -> WalkState {
+        self.histogram = histogram;
+        self
+    }
+
+    // Record the files that haven't been modified or accessed in each of the given number of
+    // days, relative to when the scan started.
+    pub fn age_buckets(mut self, age_buckets: Vec<u32>) -> WalkState {
+        self.age_buckets = age_buckets;
+        self
+    }
+
+    // Record the given number of largest files within each directory and across the scan.
+    pub fn largest_files(mut self, limit: Option<usize>) -> WalkState {
+        self.largest_files_limit = limit;
+        self
+    }
+
+    // Periodically save a checkpoint that the scan can be resumed from.
+    pub fn checkpoint(mut self, checkpointer: Option<Checkpointer>) -> WalkState {
+        self.checkpointer = checkpointer;
+        self
+    }
+
+    // Use the previous statistics for directories that the walker found to be unchanged. The
+    // walker must be given the same baseline.
+    pub fn baseline(mut self, baseline: Option<Arc<Baseline>>) -> WalkState {
+        self.baseline = baseline;
+        self
+    }
+
+    // Count the errors the walker recorded within each directory. The walker must be given the
+    // same error log.
+    pub fn error_log(mut self, error_log: Option<Arc<ErrorLog>>) -> WalkState {
+        self.error_log = error_log;
+        self
+    }
+
+    // Write this metadata again once the scan finishes, with when it finished and whether it
+    // was interrupted.
+    pub fn metadata(mut self, metadata: Option<ScanMetadata>) -> WalkState {
+        self.metadata = metadata;
+        self
+    }
+
+    // Restore the state saved in a checkpoint. The walker must skip everything before the
+    // checkpoint's `resume_from` path.
+    pub fn resume(mut self, checkpoint: Checkpoint) -> WalkState {
+        self.started = checkpoint.started;
+        self.stack = checkpoint.stack;
+        self.seen_hard_links = checkpoint.seen_hard_links.into_iter().collect();
+        self.largest_files = checkpoint.largest_files;
+        self
+    }
+
     fn is_equivalent_path(root: &PathBuf, target: &PathBuf, depth: Option<usize>) -> bool {
         // Are these two directory paths the same, or given a depth are the first N
         // components the same?
@@ -32,48 +140,352 @@ impl WalkState {
         }
     }
 
-    pub fn add_path(&mut self, path: PathBuf, metadata: &MetadataWithSize) {
-        // println!("{} - {}", path.display(), path.is_dir());
-        match &mut self.current {
-            None => {
-                self.current = Some(DirectoryStat::from_metadata(path, metadata));
+    // Files are added to the directory they are in. Entries the walker didn't read the metadata
+    // of are skipped.
+    pub fn add_entry(&mut self, dir_entry: &WalkDirEntry) -> io::Result<()> {
+        if let Some(metadata) = &dir_entry.client_state {
+            if dir_entry.file_type.is_dir() {
+                self.add_path(dir_entry.path(), &dir_entry.file_name, metadata)?;
+            } else {
+                let parent = dir_entry.parent_path.to_path_buf();
+                self.add_path(parent, &dir_entry.file_name, metadata)?;
             }
+        }
+        Ok(())
+    }
+
+    // Directories are written once a directory outside of them is added, which fails if the
+    // output or checkpoint can't be written.
+    pub fn add_path(
+        &mut self,
+        path: PathBuf,
+        file_name: &OsStr,
+        metadata: &MetadataWithSize,
+    ) -> io::Result<()> {
+        match self.stack.last_mut() {
             Some(stat) if WalkState::is_equivalent_path(&stat.path, &path, self.depth) => {
                 // Same directory, update in place
-                if !metadata.is_dir {
-                    stat.total_size += metadata.size;
+                if metadata.is_dir {
+                    if path.parent() == Some(stat.path.as_path()) {
+                        stat.child_count += 1;
+                    }
+                } else {
+                    if path == stat.path {
+                        stat.child_count += 1;
+                    }
+                    let size = if metadata.is_duplicate_hard_link(&mut self.seen_hard_links) {
+                        stat.deduplicated_size += metadata.size;
+                        0
+                    } else {
+                        metadata.size
+                    };
+                    stat.total_size += size;
                     stat.file_count += 1;
+                    stat.add_file_extension(file_name, size);
+                    stat.add_file_owner(&metadata.metadata, size);
+                    stat.add_file_to_histogram(metadata.size);
+                    stat.add_file_age(&metadata.metadata, size, self.started);
+                    if let Some(limit) = self.largest_files_limit {
+                        let file_path = || path.join(file_name);
+                        if let Some(largest_files) = &mut stat.largest_files {
+                            add_largest_file(largest_files, limit, metadata.size, file_path);
+                        }
+                        add_largest_file(&mut self.largest_files, limit, metadata.size, file_path);
+                    }
                     if metadata.size > stat.largest_file_size {
                         stat.largest_file_size = metadata.size
                     }
-                    if let Ok(created) = metadata.metadata.created() {
-                        stat.update_latest_created(created.into());
-                    }
-                    if let Ok(accessed) = metadata.metadata.accessed() {
-                        stat.update_latest_accessed(accessed.into());
+                    stat.update_timestamps(&metadata.metadata);
+                }
+            }
+            Some(stat) if stat.path.starts_with(&path) => {
+                // A parent of the current directory. This only happens when resuming, as the
+                // directories that were being walked when the checkpoint was saved are
+                // walked again.
+            }
+            _ => {
+                // New directory! Write out every directory that isn't a parent of it, as
+                // we've seen everything beneath them.
+                while let Some(stat) = self.stack.last() {
+                    if path.starts_with(&stat.path) {
+                        break;
                     }
-                    if let Ok(modified) = metadata.metadata.modified() {
-                        stat.update_latest_modified(modified.into());
+                    let stat = self.stack.pop().unwrap();
+                    self.finish_directory(stat)?;
+                }
+                if self.checkpointer.as_ref().is_some_and(|c| c.is_due()) {
+                    self.save_checkpoint(&path)?;
+                }
+                if let Some(parent) = self.stack.last_mut() {
+                    if path.parent() == Some(parent.path.as_path()) {
+                        parent.child_count += 1;
                     }
                 }
+                let mut stat = DirectoryStat::from_metadata(path, metadata);
+                if self.extensions {
+                    stat.extensions = Some(Default::default());
+                }
+                if self.owners {
+                    stat.owners = Some(Default::default());
+                    stat.groups = Some(Default::default());
+                }
+                if self.histogram {
+                    stat.size_histogram = Some(vec![]);
+                }
+                if self.largest_files_limit.is_some() {
+                    stat.largest_files = Some(vec![]);
+                }
+                if !self.age_buckets.is_empty() {
+                    stat.age_buckets = Some(
+                        self.age_buckets
+                            .iter()
+                            .map(|days| (*days, Default::default()))
+                            .collect(),
+                    );
+                }
+                self.stack.push(stat);
             }
-            Some(stat) => {
-                // New directory! Write the current directory to the output file
-                self.writer
-                    .write_stat(stat)
-                    .expect("Error writing directory statistic");
-                self.current = Some(DirectoryStat::from_metadata(path, metadata));
+        }
+        Ok(())
+    }
+
+    // Write out every remaining directory and complete the output, once the walk has completed
+    // or been interrupted. Returns the largest files in the scan, if they were recorded.
+    pub fn finish(mut self, interrupted: bool) -> io::Result<Option<Vec<FileStat>>> {
+        while let Some(mut stat) = self.stack.pop() {
+            // Only part of the directories being walked when interrupted has been seen.
+            stat.incomplete |= interrupted;
+            self.finish_directory(stat)?;
+        }
+        if let Some(mut metadata) = self.metadata.take() {
+            metadata.finished = Some(Utc::now());
+            metadata.partial = interrupted;
+            self.writer.write_metadata(&metadata)?;
+        }
+        self.writer.finish()?;
+        // An interrupted scan can still be resumed from the last checkpoint.
+        if let (Some(checkpointer), false) = (&self.checkpointer, interrupted) {
+            checkpointer.remove()?;
+        }
+        let largest_files = self.largest_files;
+        Ok(self.largest_files_limit.map(|_| largest_files))
+    }
+
+    fn save_checkpoint(&mut self, resume_from: &Path) -> io::Result<()> {
+        self.writer.flush()?;
+        let checkpointer = self.checkpointer.as_mut().unwrap();
+        let output_length = std::fs::metadata(&checkpointer.output)?.len();
+        let mut stack = self.stack.clone();
+        if let Some(baseline) = &self.baseline {
+            // The walker won't decide to reuse these directories again when resuming, as
+            // their contents have been partially skipped.
+            for stat in &mut stack {
+                stat.reused |= baseline.is_reused(&stat.path);
+            }
+        }
+        let checkpoint = Checkpoint {
+            roots: checkpointer.roots.clone(),
+            format: checkpointer.format.clone(),
+            options: checkpointer.options.clone(),
+            started: self.started,
+            output_length,
+            resume_from: resume_from.to_path_buf(),
+            stack,
+            seen_hard_links: self.seen_hard_links.iter().copied().collect(),
+            largest_files: self.largest_files.clone(),
+        };
+        checkpointer.save(&checkpoint)
+    }
+
+    fn finish_directory(&mut self, mut stat: DirectoryStat) -> io::Result<()> {
+        if let Some(baseline) = &self.baseline {
+            let previous = match baseline.take_reused(&stat.path) {
+                Some(previous) => Some(previous),
+                None if stat.reused => baseline.get(&stat.path),
+                None => None,
+            };
+            if let Some(previous) = previous {
+                stat.reuse(previous);
+                if let (Some(limit), Some(files)) = (self.largest_files_limit, &stat.largest_files)
+                {
+                    for file in files {
+                        let file_path = || file.path.clone();
+                        add_largest_file(&mut self.largest_files, limit, file.size, file_path);
+                    }
+                }
             }
         }
+        if let Some(error_log) = &self.error_log {
+            // With --depth this includes the errors within directories beneath this one.
+            stat.errors += error_log.take_errors(&stat.path);
+        }
+        stat.finish_recursive();
+        if let Some(parent) = self.stack.last_mut() {
+            parent.merge_recursive(&stat);
+        }
+        self.writer.write_record(&stat)
     }
 }
 
-impl Drop for WalkState {
-    fn drop(&mut self) {
-        if let Some(stat) = &self.current {
-            self.writer
-                .write_stat(stat)
-                .expect("Error writing directory statistic");
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compression::Compression;
+    use crate::error_log::Operation;
+    use crate::formats::Format;
+    use crate::options::ScanOptions;
+    use crate::walker::Walker;
+    use serde_json::Value;
+    use std::fs::{self, File};
+    use std::time::Duration;
+
+    fn write_file(root: &Path, path: &str, size: usize) {
+        let path = root.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, vec![b'x'; size]).unwrap();
+    }
+
+    fn walker() -> Walker {
+        Walker::new(2, false, false, true, true)
+    }
+
+    fn json_writer(path: &Path) -> Box<dyn FormatWriter> {
+        let file = Compression::None.wrap_writer(File::create(path).unwrap());
+        Format::Json.get_writer(file).unwrap()
+    }
+
+    // Walk `root`, stopping once `stop_at` has been added as if the scan had been interrupted.
+    fn scan(root: &Path, walker: Walker, mut state: WalkState, stop_at: Option<&Path>) {
+        for dir_entry in walker.walk_dir(&root.to_path_buf()) {
+            let dir_entry = dir_entry.unwrap();
+            state.add_entry(&dir_entry).unwrap();
+            if stop_at == Some(dir_entry.path().as_path()) {
+                state.finish(true).unwrap();
+                return;
+            }
         }
+        state.finish(false).unwrap();
+    }
+
+    // Access times are left out, as they can change between scans.
+    fn read_lines(path: &Path) -> Vec<Value> {
+        fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(|line| {
+                let mut record: Value = serde_json::from_str(line).unwrap();
+                let fields = record.as_object_mut().unwrap();
+                fields.retain(|name, _| !name.ends_with("accessed"));
+                record
+            })
+            .collect()
+    }
+
+    #[test]
+    fn resuming_from_a_checkpoint_matches_an_uninterrupted_scan() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("root");
+        write_file(&root, "f1", 10);
+        write_file(&root, "a/f2", 20);
+        write_file(&root, "a/b/f3", 30);
+        write_file(&root, "c/f4", 40);
+        write_file(&root, "c/d/f5", 50);
+
+        let full = dir.path().join("full.json");
+        scan(
+            &root,
+            walker(),
+            WalkState::new(json_writer(&full), None),
+            None,
+        );
+
+        // Save a checkpoint at every directory, and stop once the walk reaches c.
+        let output = dir.path().join("output.json");
+        let checkpoint = dir.path().join("checkpoint.json");
+        let checkpointer = || {
+            let roots = vec![root.clone()];
+            let options = ScanOptions::default();
+            let (format, interval) = ("json".to_string(), Duration::ZERO);
+            Checkpointer::new(
+                checkpoint.clone(),
+                output.clone(),
+                roots,
+                format,
+                options,
+                interval,
+            )
+        };
+        let state = WalkState::new(json_writer(&output), None).checkpoint(Some(checkpointer()));
+        scan(&root, walker(), state, Some(&root.join("c")));
+        // The directories being walked were written, incomplete, after the checkpoint.
+        let interrupted = read_lines(&output);
+        let incomplete: Vec<_> = interrupted
+            .iter()
+            .filter(|record| record["incomplete"] == true)
+            .map(|record| record["path"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            incomplete,
+            [root.join("c").to_str().unwrap(), root.to_str().unwrap()]
+        );
+
+        let saved = Checkpoint::load(&checkpoint).unwrap().unwrap();
+        assert_eq!(saved.resume_from, root.join("c"));
+        let writer = Format::Json
+            .get_appending_writer(saved.open_output(&output).unwrap())
+            .unwrap();
+        let walker = walker().resume_from(Some(saved.resume_from.clone()));
+        let state = WalkState::new(writer, None)
+            .checkpoint(Some(checkpointer()))
+            .resume(saved);
+        scan(&root, walker, state, None);
+
+        assert_eq!(read_lines(&output), read_lines(&full));
+        assert!(!checkpoint.exists());
+    }
+
+    #[test]
+    fn errors_mark_the_directories_above_them_incomplete() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("root");
+        write_file(&root, "a/b/f", 10);
+        write_file(&root, "c/f", 20);
+        let output = dir.path().join("output.json");
+        let flags = |path: PathBuf| {
+            let records = read_lines(&output);
+            let record = records
+                .iter()
+                .find(|record| record["path"] == path.to_str().unwrap())
+                .unwrap();
+            (
+                record["errors"].as_u64().unwrap(),
+                record["incomplete"] == true,
+            )
+        };
+        let scan_with_error = |depth: Option<usize>| {
+            let error_log = Arc::new(ErrorLog::new(None).unwrap());
+            let error = io::Error::from(io::ErrorKind::NotFound);
+            error_log.record(
+                &root.join("a/b"),
+                &root.join("a/b/g"),
+                Operation::Metadata,
+                &error,
+            );
+            let state = WalkState::new(json_writer(&output), depth).error_log(Some(error_log));
+            scan(&root, walker(), state, None);
+        };
+
+        scan_with_error(None);
+        assert_eq!(flags(root.join("a/b")), (1, true));
+        assert_eq!(flags(root.join("a")), (0, true));
+        assert_eq!(flags(root.join("c")), (0, false));
+        assert_eq!(flags(root.clone()), (0, true));
+
+        // Only the directories directly within the root are written, with the errors beneath.
+        scan_with_error(Some(root.components().count() + 1));
+        assert_eq!(read_lines(&output).len(), 3);
+        assert_eq!(flags(root.join("a")), (1, true));
+        assert_eq!(flags(root.join("c")), (0, false));
+        assert_eq!(flags(root.clone()), (0, true));
     }
 }
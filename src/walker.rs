@@ -1,8 +1,15 @@
+use crate::baseline::Baseline;
+use crate::error_log::{ErrorLog, Operation};
+use crate::filter::Filter;
 use filesize::PathExt;
 
 use jwalk::{DirEntryIter, Parallelism};
 
-use std::path::PathBuf;
+use rayon::ThreadPool;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
 pub struct Walker {
     threads: usize,
@@ -10,10 +17,18 @@ pub struct Walker {
     ignore_hidden: bool,
     with_size: bool,
     sorted: bool,
+    filter: Filter,
+    one_file_system: bool,
+    thread_pool: Option<Arc<ThreadPool>>,
+    resume_from: Option<PathBuf>,
+    baseline: Option<Arc<Baseline>>,
+    error_log: Option<Arc<ErrorLog>>,
+    interrupted: Option<Arc<AtomicBool>>,
 }
 
 pub type WalkDir = jwalk::WalkDirGeneric<((), ClientState)>;
 pub type WalkDirIter = DirEntryIter<((), ClientState)>;
+pub type WalkDirEntry = jwalk::DirEntry<((), ClientState)>;
 pub type ClientState = Option<MetadataWithSize>;
 
 impl Walker {
@@ -30,46 +45,205 @@ impl Walker {
             ignore_hidden,
             with_size,
             sorted,
+            filter: Filter::default(),
+            one_file_system: false,
+            thread_pool: None,
+            resume_from: None,
+            baseline: None,
+            error_log: None,
+            interrupted: None,
         }
     }
 
-    pub fn walk_dir(self, path: &PathBuf) -> WalkDirIter {
+    pub fn filter(mut self, filter: Filter) -> Walker {
+        self.filter = filter;
+        self
+    }
+
+    // Don't descend into directories that are on a different filesystem to the root.
+    pub fn one_file_system(mut self, one_file_system: bool) -> Walker {
+        self.one_file_system = one_file_system;
+        self
+    }
+
+    // Walk using an existing thread pool rather than creating a new one with `threads` threads.
+    pub fn thread_pool(mut self, thread_pool: Arc<ThreadPool>) -> Walker {
+        self.thread_pool = Some(thread_pool);
+        self
+    }
+
+    // Skip everything that a sorted walk would have yielded before this directory, other than
+    // the directory's parents.
+    pub fn resume_from(mut self, resume_from: Option<PathBuf>) -> Walker {
+        self.resume_from = resume_from;
+        self
+    }
+
+    // Don't read the metadata of files within directories that are unchanged since the
+    // baseline scan.
+    pub fn baseline(mut self, baseline: Option<Arc<Baseline>>) -> Walker {
+        self.baseline = baseline;
+        self
+    }
+
+    // Record entries and files that couldn't be read, against the directory they are in.
+    // Directories whose contents couldn't be read are recorded by whatever consumes the walk, as
+    // jwalk reports them on the directory's entry.
+    pub fn error_log(mut self, error_log: Option<Arc<ErrorLog>>) -> Walker {
+        self.error_log = error_log;
+        self
+    }
+
+    // Stop reading directories once this is set, so that an interrupted walk doesn't carry on
+    // through the directories it has already queued.
+    pub fn interrupted(mut self, interrupted: Option<Arc<AtomicBool>>) -> Walker {
+        self.interrupted = interrupted;
+        self
+    }
+
+    // Has this root already been walked, before the directory we are resuming from?
+    pub fn is_finished(&self, root: &Path) -> bool {
+        match &self.resume_from {
+            Some(resume_from) => !resume_from.starts_with(root) && root < resume_from.as_path(),
+            None => false,
+        }
+    }
+
+    pub fn walk_dir(&self, path: &PathBuf) -> WalkDirIter {
         let actual_size = self.actual_size;
         let with_size = self.with_size;
         let sorted = self.sorted;
+        let filter = self.filter.clone();
+        let resume_from = self.resume_from.clone();
+        let baseline = self.baseline.clone();
+        let error_log = self.error_log.clone();
+        let interrupted = self.interrupted.clone();
+        let root_device = if self.one_file_system {
+            std::fs::metadata(path)
+                .ok()
+                .and_then(|metadata| device_id(&metadata))
+        } else {
+            None
+        };
         WalkDir::new(path)
             .follow_links(false)
             .skip_hidden(self.ignore_hidden)
             .sort(true)
-            .process_read_dir(move |_, _, _, result| {
+            .process_read_dir(move |_, dir_path, _, result| {
+                if interrupted
+                    .as_ref()
+                    .is_some_and(|interrupted| interrupted.load(Ordering::SeqCst))
+                {
+                    result.clear();
+                    return;
+                }
+                if let Some(error_log) = &error_log {
+                    for error in result.iter().filter_map(|r| r.as_ref().err()) {
+                        error_log.record_walk_error(dir_path, Operation::ReadDir, error);
+                    }
+                }
                 result.retain(|r| r.is_ok());
+                if let Some(resume_from) = &resume_from {
+                    if resume_from.starts_with(dir_path) && resume_from != dir_path {
+                        // A parent of the directory we are resuming from. Its files have already
+                        // been counted, as have any directories sorted before the resume point.
+                        result.retain(|r| {
+                            let dir_entry = r.as_ref().unwrap();
+                            let path = dir_entry.path();
+                            dir_entry.file_type.is_dir()
+                                && (resume_from.starts_with(&path) || &path > resume_from)
+                        });
+                    }
+                }
+                if !filter.is_empty() {
+                    // Excluded directories are removed here, so they are never descended into.
+                    result.retain(|r| {
+                        let dir_entry = r.as_ref().unwrap();
+                        let path = dir_entry.path();
+                        if dir_entry.file_type.is_dir() {
+                            filter.allows_dir(&path)
+                        } else {
+                            filter.allows_file(&path)
+                        }
+                    });
+                }
+                if let Some(baseline) = &baseline {
+                    if baseline.try_reuse(dir_path, result.len()) {
+                        // Only the subdirectories need to be walked, the totals for the files
+                        // are taken from the baseline.
+                        result.retain(|r| r.as_ref().unwrap().file_type.is_dir());
+                    }
+                }
                 // Sort items by their file type - files come first, then directories after.
                 if sorted {
                     result.sort_by_key(|f| f.as_ref().unwrap().file_type.is_dir());
                 }
-                if with_size {
+                if with_size || root_device.is_some() {
                     result.iter_mut().for_each(|dir_entry_result| {
                         if let Ok(dir_entry) = dir_entry_result {
-                            if let Ok(metadata) = dir_entry.metadata() {
-                                let is_dir = dir_entry.file_type.is_dir();
-                                let file_size = if is_dir {
-                                    0
-                                } else if actual_size {
-                                    match dir_entry.path().size_on_disk_fast(&metadata) {
-                                        Ok(size) => size,
-                                        Err(_) => metadata.len(),
+                            let is_dir = dir_entry.file_type.is_dir();
+                            if !with_size && !is_dir {
+                                return;
+                            }
+                            let metadata = match dir_entry.metadata() {
+                                Ok(metadata) => metadata,
+                                Err(error) => {
+                                    if let Some(error_log) = &error_log {
+                                        error_log.record_walk_error(
+                                            dir_path,
+                                            Operation::Metadata,
+                                            &error,
+                                        );
                                     }
-                                } else {
-                                    metadata.len()
-                                };
-                                dir_entry.client_state =
-                                    Some(MetadataWithSize::new(metadata, file_size, is_dir))
+                                    return;
+                                }
+                            };
+                            let skipped_mount_point = is_dir
+                                && root_device.is_some()
+                                && device_id(&metadata) != root_device;
+                            if skipped_mount_point {
+                                // Still yield the directory, but don't read its contents.
+                                dir_entry.read_children_path = None;
                             }
+                            if !with_size {
+                                return;
+                            }
+                            let file_size = if is_dir {
+                                0
+                            } else if actual_size {
+                                let path = dir_entry.path();
+                                match path.size_on_disk_fast(&metadata) {
+                                    Ok(size) => size,
+                                    Err(error) => {
+                                        // The apparent size is used instead.
+                                        if let Some(error_log) = &error_log {
+                                            error_log.record(
+                                                dir_path,
+                                                &path,
+                                                Operation::SizeOnDisk,
+                                                &error,
+                                            );
+                                        }
+                                        metadata.len()
+                                    }
+                                }
+                            } else {
+                                metadata.len()
+                            };
+                            dir_entry.client_state = Some(MetadataWithSize::new(
+                                metadata,
+                                file_size,
+                                is_dir,
+                                skipped_mount_point,
+                            ))
                         }
                     });
                 }
             })
-            .parallelism(Parallelism::RayonNewPool(self.threads))
+            .parallelism(match &self.thread_pool {
+                Some(thread_pool) => Parallelism::RayonExistingPool(thread_pool.clone()),
+                None => Parallelism::RayonNewPool(self.threads),
+            })
             .into_iter()
     }
 }
@@ -79,14 +253,68 @@ pub struct MetadataWithSize {
     pub metadata: std::fs::Metadata,
     pub size: u64,
     pub is_dir: bool,
+    pub skipped_mount_point: bool,
 }
 
 impl MetadataWithSize {
-    pub fn new(metadata: std::fs::Metadata, size: u64, is_dir: bool) -> MetadataWithSize {
+    pub fn new(
+        metadata: std::fs::Metadata,
+        size: u64,
+        is_dir: bool,
+        skipped_mount_point: bool,
+    ) -> MetadataWithSize {
         MetadataWithSize {
             metadata,
             size,
             is_dir,
+            skipped_mount_point,
         }
     }
 }
+
+impl MetadataWithSize {
+    // Has another hard link to this file already been seen? This records the file as seen.
+    #[cfg(target_family = "unix")]
+    pub fn is_duplicate_hard_link(&self, seen: &mut HashSet<(u64, u64)>) -> bool {
+        use std::os::unix::fs::MetadataExt;
+        let metadata = &self.metadata;
+        metadata.nlink() > 1 && !seen.insert((metadata.dev(), metadata.ino()))
+    }
+
+    #[cfg(not(target_family = "unix"))]
+    pub fn is_duplicate_hard_link(&self, _seen: &mut HashSet<(u64, u64)>) -> bool {
+        false
+    }
+}
+
+#[cfg(target_family = "unix")]
+fn device_id(metadata: &std::fs::Metadata) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    Some(metadata.dev())
+}
+
+#[cfg(not(target_family = "unix"))]
+fn device_id(_metadata: &std::fs::Metadata) -> Option<u64> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_directories_are_read_once_interrupted() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("root");
+        std::fs::create_dir_all(root.join("a/b")).unwrap();
+        std::fs::write(root.join("a/f"), "x").unwrap();
+        let interrupted = Arc::new(AtomicBool::new(false));
+        let walker =
+            Walker::new(2, false, false, true, true).interrupted(Some(interrupted.clone()));
+        assert_eq!(walker.walk_dir(&root).count(), 4);
+
+        // jwalk yields the root from the same callback as its contents, so nothing is yielded.
+        interrupted.store(true, Ordering::SeqCst);
+        assert_eq!(walker.walk_dir(&root).count(), 0);
+    }
+}
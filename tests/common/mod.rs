@@ -0,0 +1,73 @@
+// Helpers shared by the integration tests, which run the dirscan binary against temporary
+// directory trees.
+#![allow(dead_code)]
+
+use serde_json::Value;
+use std::ffi::OsStr;
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Output};
+
+pub fn dirscan<I, S>(args: I) -> Output
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    Command::new(env!("CARGO_BIN_EXE_dirscan"))
+        .args(args)
+        .output()
+        .expect("Error running dirscan")
+}
+
+// Run dirscan, failing the test with its output if it doesn't succeed.
+pub fn dirscan_ok<I, S>(args: I) -> Output
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    let output = dirscan(args);
+    assert!(
+        output.status.success(),
+        "dirscan failed with {}: {}",
+        output.status,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    output
+}
+
+// Temporary directories have UTF-8 paths, so they can be passed as arguments alongside strings.
+pub fn arg(path: &Path) -> &str {
+    path.to_str().unwrap()
+}
+
+// Create a file of `size` bytes, and any directories it's in.
+pub fn write_file(root: &Path, path: &str, size: usize) {
+    let path = root.join(path);
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, vec![b'x'; size]).unwrap();
+}
+
+// Every line of JSON output, including the metadata records.
+pub fn read_lines(path: &Path) -> Vec<Value> {
+    fs::read_to_string(path)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
+
+// The directories in JSON output, in the order they were written.
+pub fn read_records(path: &Path) -> Vec<Value> {
+    read_lines(path)
+        .into_iter()
+        .filter(|line| line.get("metadata").is_none())
+        .collect()
+}
+
+// The directory with this path in JSON output.
+pub fn record<'a>(records: &'a [Value], path: &Path) -> &'a Value {
+    records
+        .iter()
+        .find(|record| record["path"] == path.to_str().unwrap())
+        .unwrap_or_else(|| panic!("No record for {}", path.display()))
+}
@@ -0,0 +1,36 @@
+mod common;
+
+use common::*;
+use std::fs;
+use tempfile::TempDir;
+
+#[test]
+fn files_are_grouped_by_their_full_contents() {
+    let dir = TempDir::new().unwrap();
+    let root = dir.path().join("root");
+    // Small files are covered by their partial hash.
+    write_file(&root, "small/a", 100);
+    write_file(&root, "small/b", 100);
+    fs::write(root.join("small/c"), vec![b'y'; 100]).unwrap();
+    // Large files that only differ in the middle have the same partial hash.
+    write_file(&root, "large/a", 10_000);
+    write_file(&root, "large/b", 10_000);
+    let mut contents = vec![b'x'; 10_000];
+    contents[5_000] = b'y';
+    fs::write(root.join("large/c"), contents).unwrap();
+    let output = dir.path().join("dupes.json");
+    dirscan_ok(["dupes", arg(&root), "-o", arg(&output)]);
+
+    let groups = read_lines(&output);
+    assert_eq!(groups.len(), 2);
+    assert_eq!(groups[0]["wasted_size"], 10_000);
+    assert_eq!(
+        groups[0]["paths"],
+        serde_json::json!([root.join("large/a"), root.join("large/b")])
+    );
+    assert_eq!(groups[1]["wasted_size"], 100);
+    assert_eq!(
+        groups[1]["paths"],
+        serde_json::json!([root.join("small/a"), root.join("small/b")])
+    );
+}
@@ -0,0 +1,148 @@
+mod common;
+
+use common::*;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::fs;
+use std::io::Write;
+use tempfile::TempDir;
+
+#[test]
+fn interrupted_scans_are_reported_in_compressed_output() {
+    let dir = TempDir::new().unwrap();
+    let root = dir.path().join("root");
+    write_file(&root, "a/f", 10);
+    let output = dir.path().join("output.json");
+    dirscan_ok(["scan", arg(&root), "-o", arg(&output)]);
+
+    // Mark the record written at the end as coming from an interrupted scan.
+    let contents = fs::read_to_string(&output).unwrap();
+    let (start, trailer) = contents.trim_end().rsplit_once('\n').unwrap();
+    let trailer = trailer.replace("\"partial\":false", "\"partial\":true");
+    for (name, contents) in [
+        ("complete.json.gz", contents.clone()),
+        ("partial.json.gz", format!("{}\n{}\n", start, trailer)),
+    ] {
+        let mut encoder = GzEncoder::new(vec![], Compression::default());
+        encoder.write_all(contents.as_bytes()).unwrap();
+        fs::write(dir.path().join(name), encoder.finish().unwrap()).unwrap();
+    }
+
+    let complete = dirscan_ok(["parse", arg(&dir.path().join("complete.json.gz"))]);
+    assert!(!String::from_utf8_lossy(&complete.stderr).contains("interrupted"));
+    let partial = dirscan_ok(["parse", arg(&dir.path().join("partial.json.gz"))]);
+    assert!(String::from_utf8_lossy(&partial.stderr).contains("interrupted"));
+}
+
+// The files in the output of `parse --largest-files`, from largest to smallest.
+fn largest_files(output: &std::process::Output) -> Vec<String> {
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .skip(1)
+        .filter_map(|line| line.split_whitespace().next())
+        .map(|path| path.to_string())
+        .collect()
+}
+
+#[test]
+fn largest_files_are_listed_from_every_directory() {
+    let dir = TempDir::new().unwrap();
+    let root = dir.path().join("root");
+    write_file(&root, "a/f1", 10);
+    write_file(&root, "a/f2", 20);
+    write_file(&root, "b/f3", 30);
+    write_file(&root, "b/f4", 40);
+    let output = dir.path().join("output.json");
+    dirscan_ok([
+        "scan",
+        arg(&root),
+        "-o",
+        arg(&output),
+        "--largest-files",
+        "10",
+    ]);
+
+    let parsed = dirscan_ok(["parse", arg(&output), "--largest-files"]);
+    let expected: Vec<String> = ["b/f4", "b/f3", "a/f2", "a/f1"]
+        .iter()
+        .map(|path| root.join(path).display().to_string())
+        .collect();
+    assert_eq!(largest_files(&parsed), expected);
+    let limited = dirscan_ok(["parse", arg(&output), "--largest-files", "-l", "3"]);
+    assert_eq!(largest_files(&limited), expected[..3]);
+
+    // As many files are listed as the scan recorded for each directory.
+    let one = dir.path().join("one.json");
+    dirscan_ok(["scan", arg(&root), "-o", arg(&one), "--largest-files", "1"]);
+    let parsed = dirscan_ok(["parse", arg(&one), "--largest-files"]);
+    assert_eq!(largest_files(&parsed), expected[..1]);
+
+    // Merging the same directories keeps one file for each, and the merged output lists them all.
+    let other_root = dir.path().join("other");
+    write_file(&other_root, "c/f5", 50);
+    let other = dir.path().join("other.json");
+    dirscan_ok([
+        "scan",
+        arg(&other_root),
+        "-o",
+        arg(&other),
+        "--largest-files",
+        "1",
+    ]);
+    let merged = dir.path().join("merged.json");
+    dirscan_ok([
+        "merge",
+        arg(&one),
+        arg(&one),
+        arg(&other),
+        "-o",
+        arg(&merged),
+    ]);
+    let parsed = dirscan_ok(["parse", arg(&merged), "--largest-files"]);
+    let c = other_root.join("c/f5").display().to_string();
+    assert_eq!(
+        largest_files(&parsed),
+        [c.as_str(), &expected[0], &expected[2]]
+    );
+}
+
+#[test]
+fn invalid_entries_are_reported_by_line_or_record() {
+    let dir = TempDir::new().unwrap();
+    let root = dir.path().join("root");
+    write_file(&root, "a/f", 10);
+    write_file(&root, "b/f", 20);
+    let json = dir.path().join("output.json");
+    dirscan_ok(["scan", arg(&root), "-o", arg(&json)]);
+    let csv = dir.path().join("output.csv");
+    dirscan_ok(["scan", arg(&root), "-o", arg(&csv), "--format", "csv"]);
+
+    // Replace b, which follows the metadata and a: a JSON record with a field of the wrong
+    // type, and a CSV row missing most of its fields.
+    let replace_line = |path: &std::path::Path, number: usize, line: &str| {
+        let contents = fs::read_to_string(path).unwrap();
+        let mut lines: Vec<&str> = contents.lines().collect();
+        lines[number - 1] = line;
+        fs::write(path, lines.join("\n") + "\n").unwrap();
+    };
+    replace_line(&json, 3, r#"{"total_size":"x"}"#);
+    replace_line(&csv, 4, "1,2");
+
+    for (input, location) in [(&json, "line 3 column 17"), (&csv, "record 2 (line 4)")] {
+        let failed = dirscan(["parse", arg(input)]);
+        assert_eq!(failed.status.code(), Some(1));
+        let message = format!("Error reading {}: {}:", input.display(), location);
+        assert!(String::from_utf8_lossy(&failed.stderr).contains(&message));
+
+        let skipped = dirscan_ok(["parse", "--skip-invalid", arg(input)]);
+        let message = format!(
+            "Skipping invalid entry in {}: {}:",
+            input.display(),
+            location
+        );
+        assert!(String::from_utf8_lossy(&skipped.stderr).contains(&message));
+        // The totals only include a.
+        let stdout = String::from_utf8_lossy(&skipped.stdout);
+        assert!(stdout.contains("10B") && !stdout.contains("20B"));
+    }
+}
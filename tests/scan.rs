@@ -0,0 +1,385 @@
+mod common;
+
+use common::*;
+use std::fs;
+use std::path::PathBuf;
+use tempfile::TempDir;
+
+#[test]
+fn directories_are_written_after_everything_beneath_them() {
+    let dir = TempDir::new().unwrap();
+    let root = dir.path().join("root");
+    write_file(&root, "f1", 10);
+    write_file(&root, "a/f2", 20);
+    write_file(&root, "a/b/f3", 30);
+    write_file(&root, "c/f4", 40);
+    let output = dir.path().join("output.json");
+    dirscan_ok(["scan", arg(&root), "-o", arg(&output)]);
+
+    let records = read_records(&output);
+    let paths: Vec<PathBuf> = records
+        .iter()
+        .map(|record| PathBuf::from(record["path"].as_str().unwrap()))
+        .collect();
+    assert_eq!(
+        paths,
+        vec![
+            root.join("a/b"),
+            root.join("a"),
+            root.join("c"),
+            root.clone()
+        ]
+    );
+
+    let totals = |path: PathBuf| {
+        let record = record(&records, &path);
+        (
+            record["total_size"].as_u64().unwrap(),
+            record["recursive_total_size"].as_u64().unwrap(),
+            record["recursive_file_count"].as_u64().unwrap(),
+        )
+    };
+    assert_eq!(totals(root.join("a/b")), (30, 30, 1));
+    assert_eq!(totals(root.join("a")), (20, 50, 2));
+    assert_eq!(totals(root.join("c")), (40, 40, 1));
+    assert_eq!(totals(root), (10, 100, 4));
+}
+
+// Hard links are only detected on unix.
+#[cfg(unix)]
+#[test]
+fn hard_links_are_only_counted_once() {
+    let dir = TempDir::new().unwrap();
+    let root = dir.path().join("root");
+    write_file(&root, "a/file", 100);
+    std::fs::hard_link(root.join("a/file"), root.join("a/link")).unwrap();
+    std::fs::create_dir(root.join("b")).unwrap();
+    std::fs::hard_link(root.join("a/file"), root.join("b/link")).unwrap();
+    let output = dir.path().join("output.json");
+    dirscan_ok(["scan", arg(&root), "-o", arg(&output)]);
+
+    let records = read_records(&output);
+    let a = record(&records, &root.join("a"));
+    assert_eq!(a["total_size"], 100);
+    assert_eq!(a["file_count"], 2);
+    assert_eq!(a["deduplicated_size"], 100);
+    let b = record(&records, &root.join("b"));
+    assert_eq!(b["total_size"], 0);
+    assert_eq!(b["deduplicated_size"], 100);
+    assert_eq!(record(&records, &root)["recursive_total_size"], 100);
+}
+
+#[test]
+fn resuming_discards_output_written_after_the_checkpoint() {
+    let dir = TempDir::new().unwrap();
+    let root = dir.path().join("root");
+    write_file(&root, "f1", 10);
+    write_file(&root, "a/f2", 20);
+    write_file(&root, "b/f3", 30);
+    let full = dir.path().join("full.json");
+    dirscan_ok(["scan", arg(&root), "-o", arg(&full)]);
+
+    // The state of the scan as it reaches b: a has been written, and root is on the stack with
+    // its own files and a's recursive totals.
+    let output = dir.path().join("output.json");
+    dirscan_ok([
+        "scan",
+        arg(&root),
+        "-o",
+        arg(&output),
+        "--exclude-regex",
+        "/b$",
+    ]);
+    let lines = read_lines(&output);
+    let (a, mut in_progress) = (lines[1].clone(), lines[2].clone());
+    for field in [
+        "recursive_total_size",
+        "recursive_file_count",
+        "recursive_largest_file_size",
+        "recursive_latest_created",
+        "recursive_latest_accessed",
+        "recursive_latest_modified",
+    ] {
+        in_progress[field] = a[field].clone();
+    }
+    let contents = fs::read_to_string(&output).unwrap();
+    let written: Vec<&str> = contents.lines().take(2).collect();
+    let written = format!("{}\n", written.join("\n"));
+    // An interrupted scan leaves records after the checkpoint, the last perhaps cut short.
+    fs::write(&output, format!("{}{{\"total_size\":1,\"fi", written)).unwrap();
+
+    let checkpoint = dir.path().join("checkpoint.json");
+    let state = serde_json::json!({
+        "roots": [root],
+        "format": "json",
+        "started": {"secs_since_epoch": 0, "nanos_since_epoch": 0},
+        "output_length": written.len(),
+        "resume_from": root.join("b"),
+        "stack": [in_progress],
+        "seen_hard_links": [],
+        "largest_files": [],
+        "options": {},
+    });
+    fs::write(&checkpoint, state.to_string()).unwrap();
+    dirscan_ok([
+        "scan",
+        arg(&root),
+        "-o",
+        arg(&output),
+        "--checkpoint",
+        arg(&checkpoint),
+        "--resume",
+    ]);
+
+    let expected = read_records(&full);
+    let resumed = read_records(&output);
+    assert_eq!(resumed.len(), expected.len());
+    for (resumed, expected) in resumed.iter().zip(&expected) {
+        for field in [
+            "path",
+            "total_size",
+            "file_count",
+            "child_count",
+            "recursive_total_size",
+            "recursive_file_count",
+        ] {
+            assert_eq!(
+                resumed[field], expected[field],
+                "{} of {}",
+                field, resumed["path"]
+            );
+        }
+    }
+    assert!(!checkpoint.exists());
+}
+
+#[test]
+fn resuming_needs_a_checkpoint_of_the_same_scan() {
+    let dir = TempDir::new().unwrap();
+    let root = dir.path().join("root");
+    write_file(&root, "a/f", 10);
+    let output = dir.path().join("output.json");
+    let checkpoint = dir.path().join("checkpoint.json");
+    let scan = |extra: &[&str]| {
+        let mut args = vec!["scan", arg(&root), "-o", arg(&output)];
+        args.extend(["--checkpoint", arg(&checkpoint), "--resume"]);
+        args.extend(extra);
+        dirscan(args)
+    };
+
+    // A completed scan removes its checkpoint, so resuming it starts again.
+    let started = scan(&[]);
+    assert!(started.status.success());
+    assert!(String::from_utf8_lossy(&started.stderr).contains("starting a new scan"));
+    assert_eq!(read_records(&output).len(), 2);
+
+    let state = serde_json::json!({
+        "roots": [root],
+        "format": "json",
+        "started": {"secs_since_epoch": 0, "nanos_since_epoch": 0},
+        "output_length": 0,
+        "resume_from": root.join("a"),
+        "stack": [],
+        "seen_hard_links": [],
+        "largest_files": [],
+        "options": {"depth": 1, "filters": ["--exclude=*.log"]},
+    });
+    fs::write(&checkpoint, state.to_string()).unwrap();
+    let refused = scan(&["--depth", "2"]);
+    assert_eq!(refused.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&refused.stderr).contains("different --depth, the filters"));
+    assert!(checkpoint.exists());
+}
+
+#[test]
+fn unchanged_directories_are_reused_from_the_baseline() {
+    let dir = TempDir::new().unwrap();
+    let root = dir.path().join("root");
+    write_file(&root, "a/f", 10);
+    write_file(&root, "b/f", 20);
+    let baseline = dir.path().join("baseline.json");
+    dirscan_ok(["scan", arg(&root), "-o", arg(&baseline)]);
+
+    write_file(&root, "b/g", 5);
+    let output = dir.path().join("output.json");
+    dirscan_ok([
+        "scan",
+        arg(&root),
+        "-o",
+        arg(&output),
+        "--baseline",
+        arg(&baseline),
+    ]);
+
+    let records = read_records(&output);
+    let a = record(&records, &root.join("a"));
+    assert_eq!(a["reused"], true);
+    assert_eq!(a["total_size"], 10);
+    assert_eq!(a["file_count"], 1);
+    let b = record(&records, &root.join("b"));
+    assert_eq!(b["reused"], false);
+    assert_eq!(b["total_size"], 25);
+    assert_eq!(b["file_count"], 2);
+    assert_eq!(record(&records, &root)["recursive_total_size"], 35);
+}
+
+#[test]
+fn a_baseline_is_read_in_its_own_format_and_must_have_the_same_options() {
+    let dir = TempDir::new().unwrap();
+    let root = dir.path().join("root");
+    write_file(&root, "a/f", 10);
+    let baseline = dir.path().join("baseline.csv");
+    dirscan_ok([
+        "scan",
+        arg(&root),
+        "-o",
+        arg(&baseline),
+        "--format",
+        "csv",
+        "--extensions",
+    ]);
+
+    let output = dir.path().join("output.json");
+    let scan = |extra: &[&str]| {
+        let mut args = vec!["scan", arg(&root), "-o", arg(&output)];
+        args.extend(["--baseline", arg(&baseline)]);
+        args.extend(extra);
+        dirscan(args)
+    };
+    assert!(scan(&["--extensions"]).status.success());
+    assert_eq!(
+        record(&read_records(&output), &root.join("a"))["reused"],
+        true
+    );
+
+    let refused = scan(&[]);
+    assert_eq!(refused.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&refused.stderr)
+        .contains("The baseline was scanned with different --extensions"));
+}
+
+#[test]
+fn roots_are_written_one_after_another() {
+    let dir = TempDir::new().unwrap();
+    let first = dir.path().join("first");
+    let second = dir.path().join("second");
+    write_file(&first, "a/f", 10);
+    write_file(&first, "b/f", 20);
+    write_file(&second, "a/f", 30);
+    write_file(&second, "f", 40);
+    let output = dir.path().join("output.json");
+    dirscan_ok(["scan", arg(&second), arg(&first), "-o", arg(&output)]);
+
+    let records = read_records(&output);
+    let paths: Vec<PathBuf> = records
+        .iter()
+        .map(|record| PathBuf::from(record["path"].as_str().unwrap()))
+        .collect();
+    assert_eq!(
+        paths,
+        vec![
+            first.join("a"),
+            first.join("b"),
+            first.clone(),
+            second.join("a"),
+            second.clone()
+        ]
+    );
+    assert_eq!(record(&records, &first)["recursive_total_size"], 30);
+    assert_eq!(record(&records, &second)["recursive_total_size"], 70);
+}
+
+#[test]
+fn excluded_directories_are_not_descended_into() {
+    let dir = TempDir::new().unwrap();
+    let root = dir.path().join("root");
+    write_file(&root, "a/app.log", 10);
+    write_file(&root, "a/app.txt", 20);
+    write_file(&root, "a/cache/app.log", 30);
+    write_file(&root, "b/app.log", 40);
+    let output = dir.path().join("output.json");
+    dirscan_ok([
+        "scan",
+        arg(&root),
+        "-o",
+        arg(&output),
+        "--exclude",
+        "cache",
+        "--exclude-regex",
+        "/b$",
+        "--include",
+        "*.log",
+    ]);
+
+    let records = read_records(&output);
+    let paths: Vec<PathBuf> = records
+        .iter()
+        .map(|record| PathBuf::from(record["path"].as_str().unwrap()))
+        .collect();
+    assert_eq!(paths, vec![root.join("a"), root.clone()]);
+    assert_eq!(record(&records, &root.join("a"))["total_size"], 10);
+    assert_eq!(record(&records, &root)["recursive_total_size"], 10);
+}
+
+// Anyone running as root can read the directory anyway, so this test does nothing for them.
+#[cfg(unix)]
+#[test]
+fn unreadable_directories_are_logged_and_mark_their_ancestors_incomplete() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = TempDir::new().unwrap();
+    let root = dir.path().join("root");
+    write_file(&root, "a/f", 10);
+    write_file(&root, "a/locked/f", 20);
+    write_file(&root, "b/f", 30);
+    let locked = root.join("a/locked");
+    fs::set_permissions(&locked, fs::Permissions::from_mode(0o000)).unwrap();
+    if fs::read_dir(&locked).is_ok() {
+        return;
+    }
+    let output = dir.path().join("output.json");
+    let errors = dir.path().join("errors.json");
+    let scanned = dirscan([
+        "scan",
+        arg(&root),
+        "-o",
+        arg(&output),
+        "--errors",
+        arg(&errors),
+    ]);
+    // Only the directories directly within the root are written.
+    let depth = (root.components().count() + 1).to_string();
+    let shallow = dir.path().join("shallow.json");
+    let scanned_shallow = dirscan(["scan", arg(&root), "-o", arg(&shallow), "-d", &depth]);
+    // Let the temporary directory be removed.
+    fs::set_permissions(&locked, fs::Permissions::from_mode(0o755)).unwrap();
+    assert!(scanned.status.success());
+    assert!(scanned_shallow.status.success());
+
+    let logged = read_lines(&errors);
+    assert_eq!(logged.len(), 1);
+    assert_eq!(logged[0]["path"], arg(&locked));
+    assert_eq!(logged[0]["operation"], "read_dir");
+    assert_eq!(logged[0]["kind"], "PermissionDenied");
+    assert_eq!(logged[0]["errno"], 13);
+    assert!(String::from_utf8_lossy(&scanned.stderr).contains("PermissionDenied"));
+
+    let flags = |records: &[serde_json::Value], path: PathBuf| {
+        let record = record(records, &path);
+        (
+            record["errors"].as_u64().unwrap(),
+            record["incomplete"] == true,
+        )
+    };
+    let records = read_records(&output);
+    assert_eq!(flags(&records, root.join("a/locked")), (1, true));
+    assert_eq!(flags(&records, root.join("a")), (0, true));
+    assert_eq!(flags(&records, root.join("b")), (0, false));
+    assert_eq!(flags(&records, root.clone()), (0, true));
+    // With --depth, errors beneath a directory are counted in it.
+    let records = read_records(&shallow);
+    assert_eq!(records.len(), 3);
+    assert_eq!(flags(&records, root.join("a")), (1, true));
+    assert_eq!(flags(&records, root.join("b")), (0, false));
+    assert_eq!(flags(&records, root.clone()), (0, true));
+}
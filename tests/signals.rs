@@ -0,0 +1,61 @@
+#![cfg(unix)]
+
+mod common;
+
+use common::*;
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::Pid;
+use serde_json::Value;
+use std::io::{BufRead, BufReader, Read};
+use std::process::{Command, Stdio};
+use tempfile::TempDir;
+
+#[test]
+fn interrupted_scans_write_partial_output() {
+    let dir = TempDir::new().unwrap();
+    let root = dir.path().join("root");
+    // Far more output than a pipe holds, so the scan waits to write to stdout until it's read.
+    for index in 0..1000 {
+        write_file(&root, &format!("{:04}/f", index), 10);
+    }
+    let mut child = Command::new(env!("CARGO_BIN_EXE_dirscan"))
+        .args(["scan", arg(&root)])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdout = BufReader::new(child.stdout.take().unwrap());
+    let mut stderr = BufReader::new(child.stderr.take().unwrap());
+
+    // Signals are handled by the time the metadata is written, and the root is being walked
+    // once the first directory in it has been written.
+    let mut written = String::new();
+    stdout.read_line(&mut written).unwrap();
+    stdout.read_line(&mut written).unwrap();
+    kill(Pid::from_raw(child.id() as i32), Signal::SIGINT).unwrap();
+    let mut line = String::new();
+    while !line.contains("Stopping the scan") {
+        line.clear();
+        assert_ne!(
+            stderr.read_line(&mut line).unwrap(),
+            0,
+            "The scan wasn't stopped"
+        );
+    }
+
+    // Everything is read, so the scan never writes to a closed pipe.
+    let mut rest = String::new();
+    stdout.read_to_string(&mut rest).unwrap();
+    stderr.read_to_string(&mut String::new()).unwrap();
+    assert_eq!(child.wait().unwrap().code(), Some(130));
+    let lines: Vec<Value> = rest
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let (trailer, records) = lines.split_last().unwrap();
+    assert_eq!(trailer["metadata"]["partial"], true);
+    assert!(records.len() < 1000);
+    let last = records.last().unwrap();
+    assert_eq!(last["path"], arg(&root));
+    assert_eq!(last["incomplete"], true);
+}